@@ -0,0 +1,394 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use log::{error, info};
+use pretty_duration::pretty_duration;
+use rust_decimal::Decimal;
+use teloxide::{types::ChatId, Bot};
+
+use crate::move_alerts::MoveAlert;
+use crate::render::{self, Document};
+use crate::resolver::Resolver;
+use crate::storage::Storage;
+use crate::DataSources;
+
+/// Most alerts a single chat may keep.
+const MAX_ALERTS_PER_CHAT: usize = 20;
+
+const USAGE: &str = "Usage: /alert <ticker> >|< <price> [repeat] | /alert list | /alert del <id>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Above,
+    Below,
+}
+
+impl Condition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Condition::Above => ">",
+            Condition::Below => "<",
+        }
+    }
+
+    fn holds(&self, price: Decimal, threshold: Decimal) -> bool {
+        match self {
+            Condition::Above => price > threshold,
+            Condition::Below => price < threshold,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Condition> {
+        match s {
+            ">" => Ok(Condition::Above),
+            "<" => Ok(Condition::Below),
+            _ => Err(anyhow!("unknown condition {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PriceAlert {
+    pub id: i64,
+    pub chat_id: i64,
+    pub ticker: String,
+    pub condition: Condition,
+    pub threshold: Decimal,
+    /// Repeating alerts stay after triggering and fire again once the
+    /// condition has cleared and holds again; others are deleted.
+    pub repeat: bool,
+    /// False while a repeating alert waits for its condition to clear.
+    pub armed: bool,
+}
+
+impl PriceAlert {
+    fn describe(&self, currency: &str) -> String {
+        format!(
+            "#{} {} {} {} {}{}",
+            self.id,
+            self.ticker,
+            self.condition.as_str(),
+            self.threshold,
+            currency,
+            if self.repeat { " (repeat)" } else { "" }
+        )
+    }
+}
+
+/// The currency `ticker` is priced in: its pair's quote currency, or USD
+/// like every other ticker.
+async fn currency(resolver: &Resolver, ticker: &str) -> String {
+    match resolver.pair(ticker).await {
+        Some(pair) => pair.quote,
+        None => "USD".to_owned(),
+    }
+}
+
+/// Handles `/alert` for a chat and returns the reply.
+pub async fn handle_command(
+    args: &str,
+    chat_id: i64,
+    storage: &Storage,
+    resolver: &Resolver,
+) -> Result<String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    match args.as_slice() {
+        [] | ["list"] => {
+            let alerts = storage.alerts(chat_id)?;
+            if alerts.is_empty() {
+                Ok("No alerts".to_owned())
+            } else {
+                Ok(join_all(
+                    alerts
+                        .iter()
+                        .map(|a| async move { a.describe(&currency(resolver, &a.ticker).await) }),
+                )
+                .await
+                .join("\n"))
+            }
+        }
+        ["del", id] => {
+            let id = match id.trim_start_matches('#').parse() {
+                Ok(id) => id,
+                Err(_) => return Ok(USAGE.to_owned()),
+            };
+            if storage.delete_alert(chat_id, id)? {
+                Ok(format!("Deleted alert #{}", id))
+            } else {
+                Ok(format!("No alert #{}", id))
+            }
+        }
+        args => {
+            let (ticker, condition, threshold, repeat) = match parse_alert(args) {
+                Some(alert) => alert,
+                None => return Ok(USAGE.to_owned()),
+            };
+            if resolver.resolve(&ticker).await.is_none() {
+                return Ok(format!("Unknown ticker: {}", ticker));
+            }
+            if storage.alerts(chat_id)?.len() >= MAX_ALERTS_PER_CHAT {
+                return Ok(format!(
+                    "Alerts are limited to {} per chat",
+                    MAX_ALERTS_PER_CHAT
+                ));
+            }
+            let alert = storage.add_alert(chat_id, &ticker, condition, threshold, repeat)?;
+            let currency = currency(resolver, &ticker).await;
+            Ok(format!("Added alert {}", alert.describe(&currency)))
+        }
+    }
+}
+
+/// The ticker, condition, threshold and whether to repeat of a new alert,
+/// as in `BTC > 70000 repeat`.
+fn parse_alert(args: &[&str]) -> Option<(String, Condition, Decimal, bool)> {
+    let (ticker, condition, threshold, rest) = match args {
+        [ticker, condition, threshold, rest @ ..] => (ticker, condition, threshold, rest),
+        _ => return None,
+    };
+    let repeat = match rest {
+        [] => false,
+        ["repeat"] => true,
+        _ => return None,
+    };
+    Some((
+        ticker.to_ascii_uppercase(),
+        condition.parse().ok()?,
+        Decimal::from_str(threshold).ok()?,
+        repeat,
+    ))
+}
+
+/// Periodically checks every stored price and move alert against current
+/// prices and notifies the owning chat when one triggers.
+pub struct AlertMonitor {
+    bot: Bot,
+    storage: Arc<Storage>,
+    data_sources: Arc<DataSources>,
+}
+
+impl AlertMonitor {
+    pub fn new(bot: Bot, storage: Arc<Storage>, data_sources: Arc<DataSources>) -> AlertMonitor {
+        AlertMonitor {
+            bot,
+            storage,
+            data_sources,
+        }
+    }
+
+    pub async fn monitor(&self) {
+        loop {
+            if let Err(e) = self.check().await {
+                error!("Alert check: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }
+    }
+
+    async fn check(&self) -> Result<()> {
+        let mut by_ticker: BTreeMap<String, Vec<PriceAlert>> = BTreeMap::new();
+        for alert in self.storage.all_alerts()? {
+            by_ticker
+                .entry(alert.ticker.clone())
+                .or_default()
+                .push(alert);
+        }
+        let move_alerts = self.storage.all_move_alerts()?;
+        let mut tickers: BTreeSet<_> = by_ticker.keys().cloned().collect();
+        tickers.extend(move_alerts.iter().map(|a| a.ticker.clone()));
+
+        // Fetching also feeds the rolling series the move alerts read.
+        let prices: BTreeMap<_, _> = join_all(tickers.into_iter().map(|ticker| async move {
+            let price = self
+                .data_sources
+                .get_ticker_data(&ticker)
+                .await
+                .and_then(|t| t.last_price);
+            (ticker, price)
+        }))
+        .await
+        .into_iter()
+        .collect();
+
+        for (ticker, alerts) in by_ticker {
+            let price = match prices.get(&ticker) {
+                Some(Some(price)) => *price,
+                _ => continue,
+            };
+            for alert in alerts {
+                if let Err(e) = self.evaluate(&ticker, &alert, price).await {
+                    error!("Alert #{}: {}", alert.id, e);
+                }
+            }
+        }
+        for alert in move_alerts {
+            if let Err(e) = self.evaluate_move(&alert).await {
+                error!("Move alert #{}: {}", alert.id, e);
+            }
+        }
+        self.data_sources.series.prune_storage();
+        Ok(())
+    }
+
+    async fn evaluate_move(&self, alert: &MoveAlert) -> Result<()> {
+        let now = SystemTime::now();
+        let change = match alert.triggered(&self.data_sources.series, now) {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        info!("Move alert #{} triggered at {:+.2}%", alert.id, change);
+        self.storage.set_move_alert_triggered(alert.id, now)?;
+        let text = format!(
+            "Move alert #{}: {} moved {:+.2}% within {}",
+            alert.id,
+            alert.ticker,
+            change,
+            pretty_duration(&alert.window, None)
+        );
+        let text = Document::text(text);
+        if let Err(e) = render::send(&self.bot, ChatId(alert.chat_id), &text, None, None).await {
+            error!("Send move alert #{}: {}", alert.id, e);
+        }
+        Ok(())
+    }
+
+    async fn evaluate(&self, ticker: &str, alert: &PriceAlert, price: Decimal) -> Result<()> {
+        let holds = alert.condition.holds(price, alert.threshold);
+        if !holds {
+            if !alert.armed {
+                self.storage.set_alert_armed(alert.id, true)?;
+            }
+            return Ok(());
+        }
+        if !alert.armed {
+            return Ok(());
+        }
+        info!("Alert #{} triggered at {}", alert.id, price);
+        let currency = currency(&self.data_sources.resolver, ticker).await;
+        let text = format!(
+            "Alert #{}: {} is {} {} {} (now {:.2} {})",
+            alert.id,
+            ticker,
+            match alert.condition {
+                Condition::Above => "above",
+                Condition::Below => "below",
+            },
+            alert.threshold,
+            currency,
+            price,
+            currency
+        );
+        let text = Document::text(text);
+        // Kept until the chat has been told, so a failed send is retried at
+        // the next check.
+        if let Err(e) = render::send(&self.bot, ChatId(alert.chat_id), &text, None, None).await {
+            error!("Send alert #{}: {}", alert.id, e);
+            return Ok(());
+        }
+        if alert.repeat {
+            self.storage.set_alert_armed(alert.id, false)?;
+        } else {
+            self.storage.delete_alert(alert.chat_id, alert.id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+    use teloxide::Bot;
+    use wiremock::matchers::{body_string_contains, method, path_regex};
+    use wiremock::{Mock, MockBuilder, MockServer, ResponseTemplate};
+
+    use super::{parse_alert, AlertMonitor, Condition};
+    use crate::storage::Storage;
+    use crate::test_util::data_sources;
+
+    #[test]
+    fn alert_arguments() {
+        assert_eq!(
+            parse_alert(&["btc", ">", "70000"]),
+            Some(("BTC".to_owned(), Condition::Above, dec!(70000), false))
+        );
+        assert_eq!(
+            parse_alert(&["ETH", "<", "2500.5", "repeat"]),
+            Some(("ETH".to_owned(), Condition::Below, dec!(2500.5), true))
+        );
+    }
+
+    #[test]
+    fn bad_alert_arguments() {
+        for args in [
+            &["BTC", ">"][..],
+            &["BTC", ">=", "70000"],
+            &["BTC", ">", "70k"],
+            &["BTC", ">", "70000", "again"],
+            &["BTC", ">", "70000", "repeat", "repeat"],
+        ] {
+            assert_eq!(parse_alert(args), None, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        assert!(Condition::Above.holds(dec!(2), dec!(1)));
+        assert!(!Condition::Above.holds(dec!(1), dec!(1)));
+        assert!(Condition::Below.holds(dec!(0.5), dec!(1)));
+        assert_eq!(">".parse::<Condition>().unwrap().as_str(), ">");
+    }
+
+    fn send_message() -> MockBuilder {
+        Mock::given(method("POST")).and(path_regex("(?i)/sendmessage$"))
+    }
+
+    #[tokio::test]
+    async fn keeps_alert_until_sent() {
+        let server = MockServer::start().await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let storage = Arc::new(Storage::open(":memory:").unwrap());
+        let data_sources = Arc::new(data_sources(&server.uri(), &["BTC"]));
+        let monitor = AlertMonitor::new(bot, storage.clone(), data_sources);
+        let alert = storage
+            .add_alert(1, "BTC", Condition::Above, dec!(100), false)
+            .unwrap();
+
+        let failing = send_message()
+            .respond_with(ResponseTemplate::new(500))
+            .mount_as_scoped(&server)
+            .await;
+        monitor.evaluate("BTC", &alert, dec!(101)).await.unwrap();
+        assert_eq!(storage.alerts(1).unwrap().len(), 1);
+        drop(failing);
+
+        send_message()
+            .and(body_string_contains("above 100 USD"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 0,
+                    "chat": { "id": 1, "type": "private", "first_name": "Test" },
+                    "text": "Alert #1"
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        monitor.evaluate("BTC", &alert, dec!(101)).await.unwrap();
+        assert!(storage.alerts(1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::render::{Align, Document, Table};
+use crate::resolver::Resolver;
+
+const USAGE: &str = "Usage: /sources <ticker>";
+
+/// Handles `/sources` and returns the reply.
+pub async fn handle_command(args: &str, resolver: &Resolver) -> Result<Document> {
+    let ticker = match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [ticker] => ticker.to_ascii_uppercase(),
+        _ => return Ok(Document::text(USAGE)),
+    };
+    let source = match resolver.resolve(&ticker).await {
+        Some(source) => source,
+        None => return Ok(Document::text(format!("Unknown ticker: {}", ticker))),
+    };
+    let (aggregated, breakdown) = source.get_breakdown().await;
+    let price = |price: Option<Decimal>| {
+        price
+            .map(|price| format!("{:.2}", price))
+            .unwrap_or("N/A".to_owned())
+    };
+
+    let now = Instant::now();
+    let mut table = Table::new(&[
+        Align::Left,
+        Align::Right,
+        Align::Right,
+        Align::Right,
+        Align::Right,
+    ]);
+    table.row(["Source", "Last", "Prev", "Latency", "Age"]);
+    let mut errors = vec![];
+    for (name, ticker_data) in &breakdown {
+        table.row([
+            name.clone(),
+            price(ticker_data.last_price),
+            price(ticker_data.prev_price),
+            ticker_data
+                .latency
+                .map(|latency| format!("{}ms", latency.as_millis()))
+                .unwrap_or("-".to_owned()),
+            ticker_data
+                .fetched_at
+                .map(|at| format!("{}s", now.duration_since(at).as_secs()))
+                .unwrap_or("-".to_owned()),
+        ]);
+        errors.extend(ticker_data.errors.iter().map(|error| error.summary()));
+    }
+
+    let prices: Vec<_> = breakdown.iter().flat_map(|(_, t)| t.last_price).collect();
+    let spread = match (prices.iter().min(), prices.iter().max()) {
+        (Some(low), Some(high)) if prices.len() >= 2 && !low.is_zero() => format!(
+            ", spread {:.3}%",
+            ((high / low).to_f64().unwrap() - 1.) * 100.
+        ),
+        _ => String::new(),
+    };
+    let mut notes: Vec<_> = aggregated
+        .rejected
+        .iter()
+        .map(|rejected| format!("Dropped {}", rejected))
+        .collect();
+    notes.extend(errors);
+    let mut document = Document::text(format!(
+        "{}: {}{}",
+        ticker,
+        price(aggregated.last_price),
+        spread
+    ));
+    document.pre(table.lines().into_iter().chain(notes));
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::MockServer;
+
+    use super::{handle_command, USAGE};
+    use crate::render::Document;
+    use crate::resolver::Resolver;
+    use crate::test_util::{self, serve_binance, serve_fixture};
+
+    /// BTC from Binance and Kraken, which answer, and Coinbase, which
+    /// doesn't.
+    async fn setup() -> (Resolver, MockServer) {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        serve_fixture(
+            &server,
+            "/0/public/Ticker",
+            ("pair", "XXBTZUSD"),
+            include_str!("../tests/fixtures/kraken_ticker.json"),
+        )
+        .await;
+        let resolver = test_util::resolver(
+            &server.uri(),
+            "[[tickers]]\n\
+             name = \"BTC\"\n\
+             sources = [\n\
+                 { type = \"binance\", symbol = \"BTCUSDT\" },\n\
+                 { type = \"coinbase\", symbol = \"BTC-USD\" },\n\
+                 { type = \"kraken\", symbol = \"XXBTZUSD\" },\n\
+             ]\n",
+        );
+        (resolver, server)
+    }
+
+    #[tokio::test]
+    async fn lists_each_source() {
+        let (resolver, _server) = setup().await;
+        let document = handle_command("btc", &resolver).await.unwrap();
+        let message = document.render().concat();
+        let lines: Vec<_> = message.lines().collect();
+        assert_eq!(lines[0], "BTC: 67436\\.99, spread 0\\.012%");
+        let row = |source: &str| -> Vec<&str> {
+            let line = lines
+                .iter()
+                .find(|line| line.starts_with(source))
+                .unwrap_or_else(|| panic!("no {} in {}", source, message));
+            line.split_whitespace().collect()
+        };
+        assert_eq!(row("binance:BTCUSDT")[1..3], ["67432.78", "67945.12"]);
+        assert_eq!(row("kraken:XXBTZUSD")[1..3], ["67441.20", "67931.40"]);
+        assert_eq!(row("coinbase:BTC-USD")[1..3], ["N/A", "N/A"]);
+        assert!(row("binance:BTCUSDT")[3].ends_with("ms"));
+        assert!(
+            message.contains("coinbase:BTC-USD: unexpected response"),
+            "{}",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_ticker() {
+        let (resolver, _server) = setup().await;
+        let render = |document: Document| document.render();
+        assert_eq!(
+            render(handle_command("nope/nope", &resolver).await.unwrap()),
+            ["Unknown ticker: NOPE/NOPE"]
+        );
+        assert_eq!(
+            render(handle_command("btc eth", &resolver).await.unwrap()),
+            Document::text(USAGE).render()
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use log::info;
+use reqwest::Client;
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde_json::Value as JsonValue;
+use yahoo_finance_api::YahooConnector;
+
+use crate::config::Endpoints;
+use crate::resolver::SourceSpec;
+use crate::symbols::Exchange;
+
+/// Binance serves at most this many klines per request.
+const BINANCE_MAX_KLINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Hour,
+    Day,
+}
+
+impl Interval {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Interval::Hour => Duration::from_secs(3600),
+            Interval::Day => Duration::from_secs(3600 * 24),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Interval::Hour => "1h",
+            Interval::Day => "1d",
+        }
+    }
+}
+
+/// One OHLC bar, `time` being its open time.
+#[derive(Debug, Clone)]
+pub struct Candle {
+    pub time: SystemTime,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+}
+
+/// Whether `fetch` can get candles for the source.
+pub fn supported(spec: &SourceSpec) -> bool {
+    matches!(
+        spec,
+        SourceSpec::Yahoo(_) | SourceSpec::Exchange(Exchange::Binance, _)
+    )
+}
+
+/// Fetches up to the last `count` candles of the source, oldest first.
+pub async fn fetch(
+    client: &Client,
+    endpoints: &Endpoints,
+    yfi: Option<&YahooConnector>,
+    spec: &SourceSpec,
+    interval: Interval,
+    count: usize,
+) -> Result<Vec<Candle>> {
+    match spec {
+        SourceSpec::Yahoo(symbol) => match yfi {
+            Some(yfi) => fetch_yahoo(yfi, symbol, interval, count).await,
+            None => Err(anyhow!(
+                "Yahoo Finance is disabled while replaying a cassette"
+            )),
+        },
+        SourceSpec::Exchange(Exchange::Binance, symbol) => {
+            fetch_binance(client, &endpoints.binance, symbol, interval, count).await
+        }
+        _ => Err(anyhow!("no candles available from {:?}", spec)),
+    }
+}
+
+async fn fetch_yahoo(
+    yfi: &YahooConnector,
+    symbol: &str,
+    interval: Interval,
+    count: usize,
+) -> Result<Vec<Candle>> {
+    let span = interval.duration() * count as u32;
+    // Yahoo only takes a fixed set of ranges; pick the shortest that covers
+    // the span and trim afterwards.
+    let range = [
+        ("1d", 1),
+        ("5d", 5),
+        ("1mo", 31),
+        ("3mo", 92),
+        ("6mo", 183),
+        ("1y", 366),
+        ("2y", 731),
+        ("5y", 1827),
+        ("10y", 3653),
+    ]
+    .iter()
+    .find(|(_, days)| span <= Duration::from_secs(3600 * 24 * days))
+    .map(|(range, _)| *range)
+    .unwrap_or("max");
+    let response = yfi.get_quote_range(symbol, interval.code(), range).await?;
+    let quotes = response.quotes()?;
+    info!("Yahoo: {} {} candles", symbol, quotes.len());
+    let mut candles: Vec<_> = quotes
+        .iter()
+        .filter_map(|quote| {
+            Some(Candle {
+                time: UNIX_EPOCH + Duration::from_secs(quote.timestamp),
+                open: Decimal::from_f64(quote.open)?,
+                high: Decimal::from_f64(quote.high)?,
+                low: Decimal::from_f64(quote.low)?,
+                close: Decimal::from_f64(quote.close)?,
+            })
+        })
+        .collect();
+    if candles.len() > count {
+        candles.drain(..candles.len() - count);
+    }
+    Ok(candles)
+}
+
+async fn fetch_binance(
+    client: &Client,
+    base_url: &str,
+    symbol: &str,
+    interval: Interval,
+    count: usize,
+) -> Result<Vec<Candle>> {
+    let response: JsonValue = client
+        .get(format!(
+            "{}/api/v3/klines?symbol={}&interval={}&limit={}",
+            base_url,
+            symbol,
+            interval.code(),
+            count.min(BINANCE_MAX_KLINES)
+        ))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if response["msg"] != JsonValue::Null {
+        return Err(anyhow!("Binance: {}", response["msg"]));
+    }
+    let klines = response
+        .as_array()
+        .ok_or(anyhow!("Failed to parse Binance klines"))?;
+    info!("Binance: {} {} candles", symbol, klines.len());
+    klines.iter().map(parse_kline).collect()
+}
+
+/// Parses `[open time (ms), "open", "high", "low", "close", ...]`.
+fn parse_kline(kline: &JsonValue) -> Result<Candle> {
+    let invalid = || anyhow!("Failed to parse Binance kline {}", kline);
+    let price = |i: usize| -> Result<Decimal> {
+        Ok(Decimal::from_str(kline[i].as_str().ok_or_else(invalid)?)?)
+    };
+    let open_time = kline[0].as_u64().ok_or_else(invalid)?;
+    Ok(Candle {
+        time: UNIX_EPOCH + Duration::from_millis(open_time),
+        open: price(1)?,
+        high: price(2)?,
+        low: price(3)?,
+        close: price(4)?,
+    })
+}
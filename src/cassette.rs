@@ -0,0 +1,313 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::config::{CassetteConfig, CassetteMode, Endpoints};
+
+/// Records the upstream traffic of the shared HTTP client to a cassette
+/// file, or serves a recorded cassette back instead of the real APIs.
+///
+/// It is a local server the endpoints are pointed at: `/binance/...` stands
+/// for the configured Binance base URL and so on, so every source, the
+/// symbol listings and `CoinbaseMonitor` go through it unchanged. Yahoo
+/// Finance uses its own client and isn't captured; while replaying, it is
+/// disabled rather than queried live.
+pub struct CassetteServer {
+    listener: TcpListener,
+    state: Arc<Cassette>,
+}
+
+struct Cassette {
+    /// Upstream base URLs by the first path segment.
+    upstreams: HashMap<String, String>,
+    mode: Mode,
+}
+
+enum Mode {
+    Record {
+        client: Client,
+        file: Arc<Mutex<File>>,
+    },
+    Replay(Mutex<Recorded>),
+}
+
+/// Recorded responses by method and URL, in order, along with how many have
+/// been served. The last one is repeated once all have been.
+type Recorded = HashMap<(String, String), (Vec<Interaction>, usize)>;
+
+/// One request and its response; a cassette is a file of these, one JSON
+/// object per line.
+#[derive(Clone, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    content_type: Option<String>,
+    body: String,
+}
+
+impl CassetteServer {
+    /// Starts listening on a local port and returns the endpoints to use in
+    /// place of `upstream`.
+    pub async fn bind(
+        config: &CassetteConfig,
+        upstream: &Endpoints,
+    ) -> Result<(CassetteServer, Endpoints)> {
+        let mode = match config.mode {
+            CassetteMode::Record => {
+                // Each recording starts afresh; appending would mix sessions
+                // whose responses replay can't tell apart.
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(&config.path)
+                    .with_context(|| format!("open cassette {}", config.path))?;
+                Mode::Record {
+                    client: Client::builder()
+                        .user_agent("ireina/0.1.0")
+                        .timeout(Duration::from_secs(10))
+                        .build()?,
+                    file: Arc::new(Mutex::new(file)),
+                }
+            }
+            CassetteMode::Replay => Mode::Replay(Mutex::new(load(&config.path)?)),
+        };
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let local = format!("http://{}", listener.local_addr()?);
+        let upstreams = [
+            ("binance", &upstream.binance),
+            ("coinbase", &upstream.coinbase),
+            ("kraken", &upstream.kraken),
+            ("goldprice", &upstream.goldprice),
+        ];
+        let endpoints = Endpoints {
+            binance: format!("{}/binance", local),
+            coinbase: format!("{}/coinbase", local),
+            kraken: format!("{}/kraken", local),
+            goldprice: format!("{}/goldprice", local),
+        };
+        info!(
+            "Cassette {}: {} at {}",
+            match config.mode {
+                CassetteMode::Record => "recording",
+                CassetteMode::Replay => "replaying",
+            },
+            config.path,
+            local
+        );
+        let server = CassetteServer {
+            listener,
+            state: Arc::new(Cassette {
+                upstreams: upstreams
+                    .iter()
+                    .map(|(name, url)| (name.to_string(), url.to_string()))
+                    .collect(),
+                mode,
+            }),
+        };
+        Ok((server, endpoints))
+    }
+
+    pub async fn serve(self) {
+        let app = Router::new().fallback(handle).with_state(self.state);
+        if let Err(e) = axum::serve(self.listener, app).await {
+            error!("cassette: {}", e);
+        }
+    }
+}
+
+fn load(path: &str) -> Result<Recorded> {
+    let file = File::open(path).with_context(|| format!("open cassette {}", path))?;
+    let mut interactions = Recorded::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let interaction: Interaction = serde_json::from_str(&line)
+            .with_context(|| format!("cassette {} line {}", path, i + 1))?;
+        interactions
+            .entry((interaction.method.clone(), interaction.url.clone()))
+            .or_default()
+            .0
+            .push(interaction);
+    }
+    Ok(interactions)
+}
+
+async fn handle(
+    State(cassette): State<Arc<Cassette>>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let (upstream, rest) = path[1..].split_at(path[1..].find('/').unwrap_or(path.len() - 1));
+    let base = match cassette.upstreams.get(upstream) {
+        Some(base) => base,
+        None => return (StatusCode::NOT_FOUND, "Unknown upstream").into_response(),
+    };
+    let url = format!("{}{}", base, rest);
+    let interaction = match &cassette.mode {
+        Mode::Record { client, file } => {
+            let interaction = match forward(client, &method, &url, body).await {
+                Ok(interaction) => interaction,
+                Err(e) => {
+                    warn!("cassette: {} {}: {}", method, url, e);
+                    return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+                }
+            };
+            let line = serde_json::to_string(&interaction).unwrap();
+            let file = file.clone();
+            let written =
+                tokio::task::spawn_blocking(move || writeln!(file.lock().unwrap(), "{}", line))
+                    .await
+                    .unwrap_or_else(|e| Err(e.into()));
+            if let Err(e) = written {
+                error!("cassette: write: {}", e);
+            }
+            interaction
+        }
+        Mode::Replay(interactions) => {
+            let mut interactions = interactions.lock().unwrap();
+            match interactions.get_mut(&(method.to_string(), url.clone())) {
+                Some((recorded, served)) => {
+                    let interaction = recorded[(*served).min(recorded.len() - 1)].clone();
+                    *served += 1;
+                    interaction
+                }
+                None => {
+                    warn!("cassette: nothing recorded for {} {}", method, url);
+                    return (StatusCode::NOT_FOUND, "Not in cassette").into_response();
+                }
+            }
+        }
+    };
+    let status = StatusCode::from_u16(interaction.status).unwrap_or(StatusCode::BAD_GATEWAY);
+    match interaction.content_type {
+        Some(content_type) => (
+            status,
+            [(header::CONTENT_TYPE, content_type)],
+            interaction.body,
+        )
+            .into_response(),
+        None => (status, interaction.body).into_response(),
+    }
+}
+
+async fn forward(client: &Client, method: &Method, url: &str, body: Bytes) -> Result<Interaction> {
+    let response = client
+        .request(method.clone(), url)
+        .body(body)
+        .send()
+        .await?;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned());
+    let body = response.text().await?;
+    Ok(Interaction {
+        method: method.to_string(),
+        url: url.to_owned(),
+        status,
+        content_type,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use reqwest::Client;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::CassetteServer;
+    use crate::config::{CassetteConfig, CassetteMode, Endpoints};
+
+    async fn start(mode: CassetteMode, path: &str, upstream: &Endpoints) -> Endpoints {
+        let config = CassetteConfig {
+            mode,
+            path: path.to_owned(),
+        };
+        let (server, endpoints) = CassetteServer::bind(&config, upstream).await.unwrap();
+        tokio::spawn(server.serve());
+        endpoints
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let cassette =
+            env::temp_dir().join(format!("ireina-cassette-{}.jsonl", std::process::id()));
+        let cassette = cassette.to_str().unwrap();
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/products"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("[]", "application/json"))
+            .mount(&upstream)
+            .await;
+        let upstream_endpoints = Endpoints {
+            coinbase: upstream.uri(),
+            ..Endpoints::default()
+        };
+        let client = Client::new();
+        // Left over from an earlier session, which recording replaces.
+        std::fs::write(cassette, "not an interaction\n").unwrap();
+
+        let endpoints = start(CassetteMode::Record, cassette, &upstream_endpoints).await;
+        let response = client
+            .get(format!("{}/products", endpoints.coinbase))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "[]");
+        drop(upstream);
+
+        let endpoints = start(CassetteMode::Replay, cassette, &upstream_endpoints).await;
+        for _ in 0..2 {
+            let response = client
+                .get(format!("{}/products", endpoints.coinbase))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                response.headers()["content-type"].to_str().unwrap(),
+                "application/json"
+            );
+            assert_eq!(response.text().await.unwrap(), "[]");
+        }
+        let response = client
+            .get(format!("{}/products?page=2", endpoints.coinbase))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        std::fs::remove_file(cassette).unwrap();
+    }
+}
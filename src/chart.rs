@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::error;
+use plotters::{prelude::*, style::register_font};
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::candles::{self, Candle, Interval};
+use crate::resolver::Resolver;
+
+const USAGE: &str = "Usage: /chart <ticker> [1d|7d|1m|1y] [line|candle]";
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 450;
+
+/// Family the chart text is drawn in; see `load_font`.
+const FONT: &str = "sans-serif";
+
+#[derive(Debug, Clone, Copy)]
+enum Range {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Range {
+    fn parse(s: &str) -> Option<Range> {
+        match s {
+            "1d" => Some(Range::Day),
+            "7d" | "1w" => Some(Range::Week),
+            "1m" | "30d" => Some(Range::Month),
+            "1y" => Some(Range::Year),
+            _ => None,
+        }
+    }
+
+    fn candles(&self) -> (Interval, usize) {
+        match self {
+            Range::Day => (Interval::Hour, 24),
+            Range::Week => (Interval::Hour, 24 * 7),
+            Range::Month => (Interval::Day, 30),
+            Range::Year => (Interval::Day, 365),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Range::Day => "1d",
+            Range::Week => "7d",
+            Range::Month => "1m",
+            Range::Year => "1y",
+        }
+    }
+
+    fn time_format(&self) -> &'static str {
+        match self {
+            Range::Day => "%H:%M",
+            Range::Week | Range::Month => "%m-%d",
+            Range::Year => "%Y-%m",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Line,
+    Candle,
+}
+
+pub enum ChartReply {
+    Chart { png: Vec<u8>, caption: String },
+    Text(String),
+}
+
+/// Registers the TrueType font charts are labelled with. Charts fail to
+/// render until one is loaded.
+pub fn load_font(path: &str) -> Result<()> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow!("read font {}: {}", path, e))?;
+    // The font registry keeps the bytes for the life of the process.
+    let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+    register_font(FONT, FontStyle::Normal, bytes).map_err(|_| anyhow!("invalid font {}", path))
+}
+
+/// Handles `/chart` and returns either the rendered chart or a text reply.
+pub async fn handle_command(args: &str, resolver: &Resolver) -> Result<ChartReply> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    let (ticker, rest) = match args.split_first() {
+        Some((ticker, rest)) => (ticker.to_ascii_uppercase(), rest),
+        None => return Ok(ChartReply::Text(USAGE.to_owned())),
+    };
+    let mut range = Range::Week;
+    let mut style = Style::Line;
+    for arg in rest {
+        match (Range::parse(arg), *arg) {
+            (Some(r), _) => range = r,
+            (None, "line") => style = Style::Line,
+            (None, "candle") | (None, "candles") => style = Style::Candle,
+            _ => return Ok(ChartReply::Text(USAGE.to_owned())),
+        }
+    }
+    let specs = match resolver.specs(&ticker).await {
+        Some(specs) => specs,
+        None => return Ok(ChartReply::Text(format!("Unknown ticker: {}", ticker))),
+    };
+    let (interval, count) = range.candles();
+    let mut history = None;
+    for spec in specs.iter().filter(|spec| candles::supported(spec)) {
+        match resolver.candles(spec, interval, count).await {
+            Ok(candles) if !candles.is_empty() => {
+                history = Some((spec.to_string(), candles));
+                break;
+            }
+            Ok(_) => error!("Chart {}: no candles from {}", ticker, spec),
+            Err(e) => error!("Chart {}: {}: {}", ticker, spec, e),
+        }
+    }
+    let (source, candles) = match history {
+        Some(history) => history,
+        None => {
+            return Ok(ChartReply::Text(format!(
+                "No price history available for {}",
+                ticker
+            )))
+        }
+    };
+    let title = format!("{} {}", ticker, range.label());
+    let png = tokio::task::spawn_blocking(move || render(&title, &candles, range, style, interval))
+        .await??;
+    Ok(ChartReply::Chart {
+        png,
+        caption: format!("{} {} ({})", ticker, range.label(), source),
+    })
+}
+
+/// Draws the candles and encodes the chart as PNG.
+fn render(
+    title: &str,
+    candles: &[Candle],
+    range: Range,
+    style: Style,
+    interval: Interval,
+) -> Result<Vec<u8>> {
+    let points: Vec<_> = candles
+        .iter()
+        .filter_map(|c| {
+            Some((
+                DateTime::<Utc>::from(c.time),
+                c.open.to_f64()?,
+                c.high.to_f64()?,
+                c.low.to_f64()?,
+                c.close.to_f64()?,
+            ))
+        })
+        .collect();
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (
+            first.0,
+            last.0 + chrono::Duration::from_std(interval.duration())?,
+        ),
+        _ => return Err(anyhow!("no candles to draw")),
+    };
+    let (low, high) = points
+        .iter()
+        .map(|p| match style {
+            Style::Line => (p.4, p.4),
+            Style::Candle => (p.3, p.2),
+        })
+        .fold((f64::MAX, f64::MIN), |(low, high), (l, h)| {
+            (low.min(l), high.max(h))
+        });
+    let padding = ((high - low) * 0.05).max(high.abs() * 1e-4);
+
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, (FONT, 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(70)
+            .build_cartesian_2d(first..last, (low - padding)..(high + padding))?;
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .y_labels(8)
+            .x_label_formatter(&|time| time.format(range.time_format()).to_string())
+            .label_style((FONT, 12))
+            .draw()?;
+        match style {
+            Style::Line => {
+                chart.draw_series(LineSeries::new(
+                    points.iter().map(|p| (p.0, p.4)),
+                    BLUE.stroke_width(2),
+                ))?;
+            }
+            Style::Candle => {
+                let plot_width = WIDTH - 70 - 20;
+                let width = (plot_width * 7 / 10 / points.len() as u32).max(1);
+                chart.draw_series(points.iter().map(|p| {
+                    CandleStick::new(p.0, p.1, p.2, p.3, p.4, GREEN.filled(), RED.filled(), width)
+                }))?;
+            }
+        }
+        root.present()?;
+    }
+    encode_png(&buffer)
+}
+
+fn encode_png(rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut png = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(rgb)?;
+    }
+    Ok(png)
+}
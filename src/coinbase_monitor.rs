@@ -13,15 +13,20 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use tokio::sync::Mutex;
 
+use crate::metrics::METRICS;
+use crate::render::{Align, Document, Table};
+
 pub struct CoinbaseMonitor {
     client: Arc<Client>,
+    base_url: String,
     data: Mutex<Vec<(SystemTime, BTreeMap<String, Product>)>>,
 }
 
 impl CoinbaseMonitor {
-    pub fn new(client: Arc<Client>) -> CoinbaseMonitor {
+    pub fn new(client: Arc<Client>, base_url: String) -> CoinbaseMonitor {
         CoinbaseMonitor {
             client,
+            base_url,
             data: Mutex::new(vec![]),
         }
     }
@@ -39,6 +44,8 @@ impl CoinbaseMonitor {
                         .filter_map(|p| serde_json::from_value(p).ok())
                         .map(|p: Product| (p.id.clone(), p))
                         .collect::<BTreeMap<_, _>>();
+                    METRICS.coinbase_polls.with_label_values(&["ok"]).inc();
+                    METRICS.coinbase_products.set(products.len() as i64);
                     let mut data = self.data.lock().await;
                     let mut updated = data
                         .iter()
@@ -48,16 +55,24 @@ impl CoinbaseMonitor {
                     updated.push((now, products));
                     *data = updated;
                 }
-                Err(err) => error!("{}", err),
+                Err(err) => {
+                    METRICS.coinbase_polls.with_label_values(&["error"]).inc();
+                    error!("{}", err);
+                }
             }
             tokio::time::sleep(Duration::from_secs(3600)).await;
         }
     }
 
     pub async fn query(&self, ticker: &str) -> Option<Product> {
+        self.product(&format!("{}-USD", ticker)).await
+    }
+
+    /// A product from the last listing by its full ID, e.g. `BTC-EUR`.
+    pub async fn product(&self, id: &str) -> Option<Product> {
         let data = self.data.lock().await;
         data.last()
-            .and_then(|(_, products)| products.get(&format!("{}-USD", ticker)).cloned())
+            .and_then(|(_, products)| products.get(id).cloned())
     }
 
     pub async fn query_cmp(&self) -> Option<String> {
@@ -92,7 +107,7 @@ impl CoinbaseMonitor {
         info!("Querying Coinbase products");
         let resp_payload = self
             .client
-            .get("https://api.exchange.coinbase.com/products".to_string())
+            .get(format!("{}/products", self.base_url))
             .send()
             .await?;
         let response: JsonValue = resp_payload.json().await?;
@@ -116,3 +131,103 @@ pub struct Product {
     #[serde(flatten)]
     other: BTreeMap<String, JsonValue>,
 }
+
+impl Product {
+    /// The product's fields, one per line, as `/cbstatus` shows them.
+    pub fn document(&self) -> Document {
+        let mut table = Table::new(&[Align::Left, Align::Left]);
+        table
+            .row(["id", &self.id])
+            .row(["base_currency", &self.base_currency])
+            .row(["quote_currency", &self.quote_currency]);
+        for (name, value) in &self.other {
+            let value = match value {
+                JsonValue::String(value) => value.clone(),
+                JsonValue::Null => "-".to_owned(),
+                value => value.to_string(),
+            };
+            table.row([name.as_str(), &value]);
+        }
+        let mut document = Document::new();
+        document.bold(self.display_name.as_str()).table(&table);
+        document
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use reqwest::Client;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::CoinbaseMonitor;
+
+    async fn monitor(response: ResponseTemplate) -> (MockServer, CoinbaseMonitor) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/products"))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let monitor = CoinbaseMonitor::new(Arc::new(client), server.uri());
+        (server, monitor)
+    }
+
+    fn json(status: u16, body: &str) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_raw(body.to_owned(), "application/json")
+    }
+
+    #[tokio::test]
+    async fn products() {
+        let (_server, monitor) = monitor(json(
+            200,
+            include_str!("../tests/fixtures/coinbase_products.json"),
+        ))
+        .await;
+        let products = monitor.query_products().await.unwrap();
+        let ids: Vec<_> = products
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["BTC-USD", "ETH-EUR"]);
+    }
+
+    #[tokio::test]
+    async fn error() {
+        let (_server, monitor) =
+            monitor(json(429, r#"{"message":"Public rate limit exceeded"}"#)).await;
+        let error = monitor.query_products().await.unwrap_err();
+        assert!(error.to_string().contains("Public rate limit exceeded"));
+    }
+
+    #[tokio::test]
+    async fn not_an_array() {
+        let (_server, monitor) = monitor(json(200, r#"{"products":[]}"#)).await;
+        let error = monitor.query_products().await.unwrap_err();
+        assert!(error.to_string().contains("result is not array"));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let (_server, monitor) = monitor(json(200, "<html>")).await;
+        assert!(monitor.query_products().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let (_server, monitor) = monitor(json(200, "[]").set_delay(Duration::from_secs(1))).await;
+        let error = monitor.query_products().await.unwrap_err();
+        let error = error.downcast_ref::<reqwest::Error>().unwrap();
+        assert!(error.is_timeout());
+    }
+}
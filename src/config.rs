@@ -0,0 +1,515 @@
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::datasources::{AggregationRules, CachePolicy, ResiliencePolicy, Strategy};
+use crate::symbols::AssetPair;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Path of the SQLite database holding per-chat state.
+    #[serde(default = "default_database")]
+    pub database: String,
+    /// Whether the rolling price series behind move alerts is also kept in
+    /// the database, so windows aren't empty right after a restart.
+    #[serde(default)]
+    pub persist_price_series: bool,
+    /// TrueType font used to label `/chart` images.
+    #[serde(default = "default_chart_font")]
+    pub chart_font: String,
+    /// How tickers with several sources combine them, unless the ticker
+    /// overrides it.
+    #[serde(default)]
+    pub aggregation: AggregationConfig,
+    /// How long source results are reused before asking upstream again.
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Retries and back-off for sources that fail.
+    #[serde(default)]
+    pub resilience: ResilienceConfig,
+    /// Where the exchange APIs are reached.
+    #[serde(default)]
+    pub endpoints: Endpoints,
+    /// Records upstream traffic to, or replays it from, a file; normally
+    /// absent.
+    pub cassette: Option<CassetteConfig>,
+    /// Telegram user IDs allowed to use admin commands such as `/health`.
+    #[serde(default)]
+    pub admins: Vec<u64>,
+    /// Chat that source errors are reported to in full; without it they are
+    /// only logged.
+    pub error_reports: Option<ErrorReportConfig>,
+    /// Local HTTP server for monitoring and the price API; not started
+    /// unless configured.
+    pub http: Option<HttpConfig>,
+    /// Long-term price history; not kept unless configured.
+    pub history: Option<HistoryConfig>,
+    pub tickers: Vec<TickerConfig>,
+}
+
+fn default_database() -> String {
+    "ireina.db".to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct CacheConfig {
+    pub ttl_secs: u64,
+    /// How long past the TTL an old result is still served while it is
+    /// refreshed in the background.
+    pub stale_secs: u64,
+    /// How long a failed query is remembered.
+    pub error_ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        let policy = CachePolicy::default();
+        CacheConfig {
+            ttl_secs: policy.ttl.as_secs(),
+            stale_secs: policy.stale.as_secs(),
+            error_ttl_secs: policy.error_ttl.as_secs(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn policy(&self) -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_secs(self.ttl_secs),
+            stale: Duration::from_secs(self.stale_secs),
+            error_ttl: Duration::from_secs(self.error_ttl_secs),
+        }
+    }
+}
+
+/// Most retries a query may make; more would keep callers waiting anyway.
+const MAX_RETRIES: u32 = 10;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ResilienceConfig {
+    /// Further attempts after a timeout, connection failure or server error.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub backoff_ms: u64,
+    /// Failed queries in a row after which a source is skipped.
+    pub failure_threshold: u32,
+    /// How long a failing source is skipped.
+    pub cooloff_secs: u64,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> ResilienceConfig {
+        let policy = ResiliencePolicy::default();
+        ResilienceConfig {
+            retries: policy.retries,
+            backoff_ms: policy.backoff.as_millis() as u64,
+            failure_threshold: policy.failure_threshold,
+            cooloff_secs: policy.cooloff.as_secs(),
+        }
+    }
+}
+
+impl ResilienceConfig {
+    pub fn policy(&self) -> ResiliencePolicy {
+        ResiliencePolicy {
+            retries: self.retries,
+            backoff: Duration::from_millis(self.backoff_ms),
+            failure_threshold: self.failure_threshold,
+            cooloff: Duration::from_secs(self.cooloff_secs),
+        }
+    }
+}
+
+/// Base URLs of the upstream APIs, without a trailing slash. Only worth
+/// changing to go through a mirror or proxy, or to test against a mock.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Endpoints {
+    pub binance: String,
+    pub coinbase: String,
+    pub kraken: String,
+    pub goldprice: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints {
+            binance: "https://api-gcp.binance.com".to_owned(),
+            coinbase: "https://api.exchange.coinbase.com".to_owned(),
+            kraken: "https://api.kraken.com".to_owned(),
+            goldprice: "https://data-asg.goldprice.org".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to listen on, e.g. `127.0.0.1:8080`.
+    pub listen: SocketAddr,
+    /// Whether prices are also served as JSON under `/v1`.
+    #[serde(default)]
+    pub api: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorReportConfig {
+    /// Telegram chat ID, e.g. of an admin or a private group.
+    pub chat: i64,
+    /// How often the errors collected in between are sent.
+    #[serde(default = "default_report_interval_secs")]
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Path of the SQLite database the history is written to, kept apart
+    /// from the per-chat state as it grows without bound.
+    pub database: String,
+    /// How many days of daily candles to import when a source has none yet.
+    #[serde(default = "default_backfill_days")]
+    pub backfill_days: usize,
+}
+
+fn default_chart_font() -> String {
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_owned()
+}
+
+fn default_backfill_days() -> usize {
+    365
+}
+
+fn default_report_interval_secs() -> u64 {
+    900
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TickerConfig {
+    pub name: String,
+    /// Canonical pair (e.g. `BTC/USD`) used to look up the native symbol of
+    /// exchange sources that don't give one explicitly. Looked up once at
+    /// startup, not again when the listings are refreshed.
+    pub pair: Option<String>,
+    pub aggregation: Option<AggregationConfig>,
+    pub sources: Vec<SourceConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregationConfig {
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Sources further than this many percent from the median are dropped.
+    pub max_deviation_percent: Option<Decimal>,
+    /// Sources further than this many median absolute deviations from the
+    /// median are dropped.
+    pub max_deviation_mads: Option<Decimal>,
+    /// Fewest sources that must be left for a price to be reported.
+    #[serde(default)]
+    pub quorum: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SourceConfig {
+    Binance { symbol: Option<String> },
+    Coinbase { symbol: Option<String> },
+    Kraken { symbol: Option<String> },
+    Yahoo { symbol: String },
+    Goldprice { metal: String, currency: String },
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("read config {}", path))?;
+        let config: Config =
+            toml::from_str(&content).with_context(|| format!("parse config {}", path))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid config {}", path))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.tickers.is_empty() {
+            return Err(anyhow!("no tickers configured"));
+        }
+        if let Some(history) = &self.history {
+            if history.database.trim().is_empty() {
+                return Err(anyhow!("history: database is empty"));
+            }
+            if history.backfill_days == 0 {
+                return Err(anyhow!("history: backfill_days must be positive"));
+            }
+        }
+        if let Some(error_reports) = &self.error_reports {
+            if error_reports.interval_secs == 0 {
+                return Err(anyhow!("error_reports: interval_secs must be positive"));
+            }
+        }
+        if self.resilience.retries > MAX_RETRIES {
+            return Err(anyhow!(
+                "resilience: retries must be at most {}",
+                MAX_RETRIES
+            ));
+        }
+        for (name, url) in [
+            ("binance", &self.endpoints.binance),
+            ("coinbase", &self.endpoints.coinbase),
+            ("kraken", &self.endpoints.kraken),
+            ("goldprice", &self.endpoints.goldprice),
+        ] {
+            if url.ends_with('/') {
+                return Err(anyhow!("endpoints: {} must not end with /", name));
+            }
+        }
+        self.aggregation
+            .validate()
+            .map_err(|e| anyhow!("aggregation: {}", e))?;
+        let mut names = HashSet::new();
+        for (i, ticker) in self.tickers.iter().enumerate() {
+            let entry = format!("tickers[{}] ({})", i, ticker.name);
+            if ticker.name.trim().is_empty() {
+                return Err(anyhow!("{}: name is empty", entry));
+            }
+            if !names.insert(ticker.name.to_ascii_uppercase()) {
+                return Err(anyhow!("{}: duplicate ticker name", entry));
+            }
+            if let Some(pair) = &ticker.pair {
+                pair.parse::<AssetPair>()
+                    .map_err(|e| anyhow!("{}: pair: {}", entry, e))?;
+            }
+            if let Some(aggregation) = &ticker.aggregation {
+                aggregation
+                    .validate()
+                    .map_err(|e| anyhow!("{}: aggregation: {}", entry, e))?;
+            }
+            if ticker.sources.is_empty() {
+                return Err(anyhow!("{}: no sources configured", entry));
+            }
+            // The ticker's own quorum or the global one, which applies to
+            // single-source tickers as well.
+            let quorum = ticker
+                .aggregation
+                .as_ref()
+                .unwrap_or(&self.aggregation)
+                .quorum;
+            if quorum > ticker.sources.len() {
+                return Err(anyhow!(
+                    "{}: quorum of {} exceeds the number of sources",
+                    entry,
+                    quorum
+                ));
+            }
+            for (j, source) in ticker.sources.iter().enumerate() {
+                source
+                    .validate(ticker.pair.is_some())
+                    .map_err(|e| anyhow!("{}: sources[{}]: {}", entry, j, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AggregationConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("max_deviation_percent", self.max_deviation_percent),
+            ("max_deviation_mads", self.max_deviation_mads),
+        ] {
+            if value.is_some_and(|value| value <= Decimal::ZERO) {
+                return Err(anyhow!("{} must be positive", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> AggregationRules {
+        AggregationRules {
+            strategy: self.strategy,
+            max_deviation_percent: self.max_deviation_percent,
+            max_deviation_mads: self.max_deviation_mads,
+            quorum: self.quorum,
+        }
+    }
+}
+
+impl SourceConfig {
+    fn validate(&self, has_pair: bool) -> Result<()> {
+        match self {
+            SourceConfig::Binance { symbol }
+            | SourceConfig::Coinbase { symbol }
+            | SourceConfig::Kraken { symbol } => match symbol {
+                Some(symbol) if symbol.trim().is_empty() => {
+                    return Err(anyhow!("symbol is empty"));
+                }
+                None if !has_pair => {
+                    return Err(anyhow!("symbol is required when the ticker has no pair"));
+                }
+                _ => {}
+            },
+            SourceConfig::Yahoo { symbol } => {
+                if symbol.trim().is_empty() {
+                    return Err(anyhow!("symbol is empty"));
+                }
+            }
+            SourceConfig::Goldprice { metal, currency } => {
+                if metal.trim().is_empty() {
+                    return Err(anyhow!("metal is empty"));
+                }
+                if currency.trim().is_empty() {
+                    return Err(anyhow!("currency is empty"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    const BTC: &str = "[[tickers]]\n\
+                       name = \"BTC\"\n\
+                       sources = [{ type = \"binance\", symbol = \"BTCUSDT\" }]\n";
+
+    /// Why `config` (TOML) is invalid, if it is.
+    fn error(config: &str) -> Option<String> {
+        let config: Config = toml::from_str(config).unwrap();
+        config.validate().err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(error(BTC), None);
+        let quorum = format!("[aggregation]\nquorum = 1\n{}", BTC);
+        assert_eq!(error(&quorum), None);
+    }
+
+    #[test]
+    fn no_tickers() {
+        assert_eq!(
+            error("tickers = []").as_deref(),
+            Some("no tickers configured")
+        );
+    }
+
+    #[test]
+    fn invalid_settings() {
+        for (settings, expected) in [
+            ("[history]\ndatabase = \" \"", "history: database is empty"),
+            (
+                "[history]\ndatabase = \"history.db\"\nbackfill_days = 0",
+                "history: backfill_days must be positive",
+            ),
+            (
+                "[error_reports]\nchat = 1\ninterval_secs = 0",
+                "error_reports: interval_secs must be positive",
+            ),
+            (
+                "[resilience]\nretries = 11",
+                "resilience: retries must be at most 10",
+            ),
+            (
+                "[endpoints]\nkraken = \"http://localhost/\"",
+                "endpoints: kraken must not end with /",
+            ),
+            (
+                "[aggregation]\nmax_deviation_percent = 0",
+                "aggregation: max_deviation_percent must be positive",
+            ),
+            (
+                "[aggregation]\nmax_deviation_mads = -1",
+                "aggregation: max_deviation_mads must be positive",
+            ),
+            (
+                "[aggregation]\nquorum = 2",
+                "tickers[0] (BTC): quorum of 2 exceeds the number of sources",
+            ),
+        ] {
+            let config = format!("{}\n{}", settings, BTC);
+            assert_eq!(error(&config).as_deref(), Some(expected), "{}", settings);
+        }
+    }
+
+    #[test]
+    fn invalid_tickers() {
+        for (ticker, expected) in [
+            (
+                "name = \" \"\nsources = [{ type = \"yahoo\", symbol = \"SPY\" }]",
+                "tickers[1] ( ): name is empty",
+            ),
+            (
+                "name = \"btc\"\nsources = [{ type = \"yahoo\", symbol = \"SPY\" }]",
+                "tickers[1] (btc): duplicate ticker name",
+            ),
+            (
+                "name = \"ETH\"\npair = \"/USD\"\nsources = [{ type = \"kraken\" }]",
+                "tickers[1] (ETH): pair: invalid asset pair \"/USD\"",
+            ),
+            (
+                "name = \"ETH\"\nsources = []",
+                "tickers[1] (ETH): no sources configured",
+            ),
+            (
+                "name = \"ETH\"\n\
+                 aggregation = { max_deviation_percent = 0 }\n\
+                 sources = [{ type = \"kraken\", symbol = \"XETHZUSD\" }]",
+                "tickers[1] (ETH): aggregation: max_deviation_percent must be positive",
+            ),
+            (
+                "name = \"ETH\"\n\
+                 aggregation = { quorum = 2 }\n\
+                 sources = [{ type = \"kraken\", symbol = \"XETHZUSD\" }]",
+                "tickers[1] (ETH): quorum of 2 exceeds the number of sources",
+            ),
+            (
+                "name = \"ETH\"\nsources = [{ type = \"kraken\", symbol = \"\" }]",
+                "tickers[1] (ETH): sources[0]: symbol is empty",
+            ),
+            (
+                "name = \"ETH\"\nsources = [{ type = \"coinbase\" }]",
+                "tickers[1] (ETH): sources[0]: symbol is required when the ticker has no pair",
+            ),
+            (
+                "name = \"SPY\"\nsources = [{ type = \"yahoo\", symbol = \" \" }]",
+                "tickers[1] (SPY): sources[0]: symbol is empty",
+            ),
+            (
+                "name = \"XAU\"\nsources = [{ type = \"goldprice\", metal = \"\", currency = \"USD\" }]",
+                "tickers[1] (XAU): sources[0]: metal is empty",
+            ),
+            (
+                "name = \"XAU\"\nsources = [{ type = \"goldprice\", metal = \"xau\", currency = \"\" }]",
+                "tickers[1] (XAU): sources[0]: currency is empty",
+            ),
+        ] {
+            let config = format!("{}[[tickers]]\n{}\n", BTC, ticker);
+            assert_eq!(error(&config).as_deref(), Some(expected), "{}", ticker);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+
+use crate::datasources::TickerData;
+use crate::fx;
+use crate::DataSources;
+
+const USAGE: &str = "Usage: /convert <amount> <from> <to> (e.g. /convert 0.25 BTC EUR)";
+
+/// Where conversions get their prices from.
+#[async_trait]
+pub trait Prices: Sync {
+    /// The ticker's data, or `None` if it is unknown.
+    async fn ticker_data(&self, ticker: &str) -> Option<TickerData>;
+
+    /// The USD value of one unit of a fiat currency.
+    async fn usd_rate(&self, currency: &str) -> Option<Decimal>;
+}
+
+#[async_trait]
+impl Prices for DataSources {
+    async fn ticker_data(&self, ticker: &str) -> Option<TickerData> {
+        self.get_ticker_data(ticker).await
+    }
+
+    async fn usd_rate(&self, currency: &str) -> Option<Decimal> {
+        self.fx.usd_rate(currency).await
+    }
+}
+
+/// Handles `/convert` and returns the reply.
+pub async fn handle_command(args: &str, data_sources: &impl Prices) -> Result<String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    let (amount, from, to) = match args.as_slice() {
+        [amount, from, to] | [amount, from, "to", to] | [amount, from, "in", to] => {
+            match Decimal::from_str(amount) {
+                Ok(amount) if amount > Decimal::ZERO => {
+                    (amount, from.to_ascii_uppercase(), to.to_ascii_uppercase())
+                }
+                _ => return Ok(USAGE.to_owned()),
+            }
+        }
+        _ => return Ok(USAGE.to_owned()),
+    };
+    let (rate, via) = match rate(data_sources, &from, &to).await {
+        Ok(rate) => rate,
+        Err(reply) => return Ok(reply),
+    };
+    let converted = match amount.checked_mul(rate) {
+        Some(converted) => converted,
+        None => return Ok("Amount is too large to convert".to_owned()),
+    };
+    Ok(format!(
+        "{} {} = {} {}\n1 {} = {} {}{}",
+        amount.normalize(),
+        from,
+        display(converted),
+        to,
+        from,
+        display(rate),
+        to,
+        if via { " (via USD)" } else { "" }
+    ))
+}
+
+/// How much of `to` one unit of `from` is worth, and whether it was
+/// triangulated through USD. Errors are replies for the user.
+async fn rate(
+    data_sources: &impl Prices,
+    from: &str,
+    to: &str,
+) -> std::result::Result<(Decimal, bool), String> {
+    if from == to {
+        return Ok((Decimal::ONE, false));
+    }
+    // Prefer a market that trades the pair directly, e.g. BTC/EUR.
+    if from != "USD" && to != "USD" && !(fx::is_currency(from) && fx::is_currency(to)) {
+        for (pair, inverse) in [
+            (format!("{}/{}", from, to), false),
+            (format!("{}/{}", to, from), true),
+        ] {
+            if let Some(price) = pair_price(data_sources, &pair).await {
+                let rate = if inverse {
+                    Decimal::ONE.checked_div(price)
+                } else {
+                    Some(price)
+                };
+                if let Some(rate) = rate {
+                    return Ok((rate, false));
+                }
+            }
+        }
+    }
+    let from_usd = usd_value(data_sources, from).await?;
+    let to_usd = usd_value(data_sources, to).await?;
+    if to_usd.is_zero() {
+        return Err(format!("No price for {}", to));
+    }
+    let via = from != "USD" && to != "USD";
+    let rate = from_usd
+        .checked_div(to_usd)
+        .ok_or(format!("No usable rate from {} to {}", from, to))?;
+    Ok((rate, via))
+}
+
+async fn pair_price(data_sources: &impl Prices, pair: &str) -> Option<Decimal> {
+    data_sources
+        .ticker_data(pair)
+        .await?
+        .last_price
+        .filter(|price| !price.is_zero())
+}
+
+/// The USD value of one unit of a currency or ticker.
+async fn usd_value(
+    data_sources: &impl Prices,
+    asset: &str,
+) -> std::result::Result<Decimal, String> {
+    if fx::is_currency(asset) {
+        return data_sources
+            .usd_rate(asset)
+            .await
+            .ok_or(format!("No exchange rate for {}", asset));
+    }
+    match data_sources.ticker_data(asset).await {
+        Some(ticker_data) => ticker_data
+            .last_price
+            .ok_or(format!("No price for {}", asset)),
+        None => Err(format!("Unknown asset: {}", asset)),
+    }
+}
+
+/// Rounds to cents for amounts of at least one unit and to six significant
+/// digits below that.
+fn display(value: Decimal) -> Decimal {
+    if value.abs() >= Decimal::ONE {
+        value.round_dp(2)
+    } else {
+        value.round_sf(6).unwrap_or(value).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{handle_command, rate, Prices, USAGE};
+    use crate::datasources::TickerData;
+
+    /// Fixed prices by ticker, and USD rates by currency.
+    #[derive(Default)]
+    struct Fixed {
+        prices: HashMap<&'static str, Option<Decimal>>,
+        rates: HashMap<&'static str, Decimal>,
+    }
+
+    #[async_trait]
+    impl Prices for Fixed {
+        async fn ticker_data(&self, ticker: &str) -> Option<TickerData> {
+            Some(TickerData {
+                last_price: *self.prices.get(ticker)?,
+                ..Default::default()
+            })
+        }
+
+        async fn usd_rate(&self, currency: &str) -> Option<Decimal> {
+            if currency == "USD" {
+                return Some(Decimal::ONE);
+            }
+            self.rates.get(currency).copied()
+        }
+    }
+
+    fn fixed(prices: &[(&'static str, Decimal)], rates: &[(&'static str, Decimal)]) -> Fixed {
+        Fixed {
+            prices: prices.iter().map(|&(t, p)| (t, Some(p))).collect(),
+            rates: rates.iter().copied().collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn direct_and_inverse_pairs() {
+        let prices = fixed(&[("BTC/EUR", dec!(60000))], &[]);
+        assert_eq!(rate(&prices, "BTC", "EUR").await, Ok((dec!(60000), false)));
+        assert_eq!(
+            rate(&prices, "EUR", "BTC").await,
+            Ok((Decimal::ONE / dec!(60000), false))
+        );
+        assert_eq!(rate(&prices, "BTC", "BTC").await, Ok((Decimal::ONE, false)));
+    }
+
+    #[tokio::test]
+    async fn triangulates_through_usd() {
+        let prices = fixed(
+            &[
+                ("BTC", dec!(60000)),
+                ("ETH", dec!(3000)),
+                ("EUR/GBP", dec!(999)),
+            ],
+            &[("EUR", dec!(1.1)), ("GBP", dec!(1.375))],
+        );
+        assert_eq!(rate(&prices, "ETH", "BTC").await, Ok((dec!(0.05), true)));
+        assert_eq!(rate(&prices, "BTC", "USD").await, Ok((dec!(60000), false)));
+        assert_eq!(
+            rate(&prices, "BTC", "EUR").await,
+            Ok((dec!(60000) / dec!(1.1), true))
+        );
+        assert_eq!(
+            rate(&prices, "USD", "GBP").await,
+            Ok((Decimal::ONE / dec!(1.375), false))
+        );
+        // Fiat pairs go by FX rates, not by tickers named like them.
+        assert_eq!(rate(&prices, "EUR", "GBP").await, Ok((dec!(0.8), true)));
+    }
+
+    #[tokio::test]
+    async fn missing_prices() {
+        let mut prices = fixed(&[("BTC", dec!(60000))], &[]);
+        prices.prices.insert("ETH", None);
+        assert_eq!(
+            rate(&prices, "FOO", "BTC").await,
+            Err("Unknown asset: FOO".to_owned())
+        );
+        assert_eq!(
+            rate(&prices, "BTC", "CHF").await,
+            Err("No exchange rate for CHF".to_owned())
+        );
+        assert_eq!(
+            rate(&prices, "ETH", "USD").await,
+            Err("No price for ETH".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn replies() {
+        let prices = fixed(&[("BTC", dec!(60000)), ("ETH", dec!(3000))], &[]);
+        assert_eq!(
+            handle_command("2 eth in btc", &prices).await.unwrap(),
+            "2 ETH = 0.1 BTC\n1 ETH = 0.05 BTC (via USD)"
+        );
+        assert_eq!(
+            handle_command("2 FOO BTC", &prices).await.unwrap(),
+            "Unknown asset: FOO"
+        );
+        for args in ["", "1 BTC", "-1 BTC USD", "one BTC USD", "1 BTC into USD"] {
+            assert_eq!(
+                handle_command(args, &prices).await.unwrap(),
+                USAGE,
+                "{}",
+                args
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let prices = fixed(&[("BTC", dec!(100000000000000000000))], &[]);
+        assert_eq!(
+            handle_command("100000000000000000000 BTC USD", &prices)
+                .await
+                .unwrap(),
+            "Amount is too large to convert"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use log::error;
+
+use crate::fx::{self, CURRENCIES};
+use crate::storage::Storage;
+
+const USAGE: &str = "Usage: /currency [<code>|reset]";
+
+/// Handles `/currency` for a chat and returns the reply.
+pub fn handle_command(args: &str, chat_id: i64, storage: &Storage) -> Result<String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    match args.as_slice() {
+        [] => Ok(format!(
+            "Display currency: {}\nAvailable: {}",
+            chat_currency(storage, chat_id),
+            CURRENCIES.join(" ")
+        )),
+        ["reset"] => {
+            storage.set_currency(chat_id, None)?;
+            Ok("Display currency reset to USD".to_owned())
+        }
+        [code] => {
+            let code = code.to_ascii_uppercase();
+            if !fx::is_currency(&code) {
+                return Ok(format!(
+                    "Unknown currency {}, available: {}",
+                    code,
+                    CURRENCIES.join(" ")
+                ));
+            }
+            storage.set_currency(chat_id, Some(&code))?;
+            Ok(format!("Display currency set to {}", code))
+        }
+        _ => Ok(USAGE.to_owned()),
+    }
+}
+
+/// The currency the chat's prices are shown in, USD unless it picked one.
+pub fn chat_currency(storage: &Storage, chat_id: i64) -> String {
+    match storage.currency(chat_id) {
+        Ok(currency) => currency.unwrap_or_else(|| "USD".to_owned()),
+        Err(e) => {
+            error!("currency: {}", e);
+            "USD".to_owned()
+        }
+    }
+}
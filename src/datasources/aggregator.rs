@@ -1,38 +1,240 @@
 use async_trait::async_trait;
 use futures::future::join_all;
 use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde::Deserialize;
 
 use super::datasource::{TickerData, TickerDataSource};
+use super::error::{ErrorKind, SourceError};
+
+/// Fewer prices than this can't tell which of them is the outlier.
+const MIN_PRICES_FOR_REJECTION: usize = 3;
+
+/// How the prices left after outlier rejection become one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    Median,
+    /// Mean weighted by each source's 24h volume; sources that don't report
+    /// volume are left out unless none do, in which case it's the median,
+    /// as it is if the sums overflow.
+    VolumeWeighted,
+    /// Mean after dropping the highest and lowest price, given at least
+    /// three; the median if the sum overflows.
+    TrimmedMean,
+    /// The first source, in configured order, that has a price.
+    Priority,
+}
+
+/// How sources are combined and how those that disagree with the rest are
+/// handled.
+#[derive(Debug, Clone, Default)]
+pub struct AggregationRules {
+    pub strategy: Strategy,
+    /// Drop prices further than this many percent from the median.
+    pub max_deviation_percent: Option<Decimal>,
+    /// Drop prices further than this many median absolute deviations from
+    /// the median.
+    pub max_deviation_mads: Option<Decimal>,
+    /// Report no price unless at least this many sources are left.
+    pub quorum: usize,
+}
 
 pub struct Aggregator {
     sources: Vec<Box<dyn TickerDataSource + Sync>>,
+    rules: AggregationRules,
 }
 
 impl Aggregator {
-    pub fn new(sources: Vec<Box<dyn TickerDataSource + Sync>>) -> Aggregator {
-        Aggregator { sources }
+    pub fn new(
+        sources: Vec<Box<dyn TickerDataSource + Sync>>,
+        rules: AggregationRules,
+    ) -> Aggregator {
+        Aggregator { sources, rules }
     }
-}
 
-#[async_trait]
-impl TickerDataSource for Aggregator {
-    async fn get_ticker_data(&self) -> TickerData {
-        let prices = join_all(self.sources.iter().map(|s| s.get_ticker_data())).await;
-        let last_price_vec: Vec<_> = prices.iter().flat_map(|t| t.last_price).collect();
-        let prev_price_vec: Vec<_> = prices.iter().flat_map(|t| t.prev_price).collect();
+    /// Combines the kept sources' values of one field per the strategy.
+    fn combine(
+        &self,
+        kept: &[&TickerData],
+        value: impl Fn(&TickerData) -> Option<Decimal>,
+    ) -> Option<Decimal> {
+        match self.rules.strategy {
+            Strategy::Median => median(kept.iter().flat_map(|t| value(t))),
+            Strategy::VolumeWeighted => {
+                let weighted: Vec<_> = kept
+                    .iter()
+                    .filter_map(|t| Some((value(t)?, t.volume?)))
+                    .filter(|(_, volume)| !volume.is_zero())
+                    .collect();
+                weighted_mean(&weighted).or_else(|| median(kept.iter().flat_map(|t| value(t))))
+            }
+            Strategy::TrimmedMean => {
+                let mut values: Vec<_> = kept.iter().flat_map(|t| value(t)).collect();
+                values.sort();
+                if values.len() >= 3 {
+                    values = values[1..values.len() - 1].to_vec();
+                }
+                weighted_mean(
+                    &values
+                        .iter()
+                        .map(|&v| (v, Decimal::ONE))
+                        .collect::<Vec<_>>(),
+                )
+                .or_else(|| median(values.into_iter()))
+            }
+            Strategy::Priority => kept.first().and_then(|t| value(t)),
+        }
+    }
+
+    /// Why `price` is an outlier against `median`, if it is one. A deviation
+    /// too large to measure, or any deviation from a zero MAD, is one.
+    fn outlier(&self, price: Decimal, median: Decimal, mad: Decimal) -> Option<String> {
+        let deviation = match price.checked_sub(median) {
+            Some(deviation) if deviation.is_zero() => return None,
+            Some(deviation) => deviation.abs(),
+            None => return Some("too far from median".to_owned()),
+        };
+        if let Some(max) = self.rules.max_deviation_percent {
+            match deviation
+                .checked_div(median)
+                .and_then(|ratio| ratio.checked_mul(Decimal::ONE_HUNDRED))
+            {
+                Some(percent) if percent <= max => {}
+                Some(percent) => return Some(format!("{:.2}% from median", percent)),
+                None => return Some("too far from median".to_owned()),
+            }
+        }
+        if let Some(max) = self.rules.max_deviation_mads {
+            match deviation.checked_div(mad) {
+                Some(mads) if mads <= max => {}
+                Some(mads) => return Some(format!("{:.1} MADs from median", mads)),
+                None => return Some("too far from median".to_owned()),
+            }
+        }
+        None
+    }
+
+    fn aggregate(&self, prices: &[TickerData]) -> TickerData {
+        let mut errors: Vec<_> = prices
+            .iter()
+            .flat_map(|t| t.errors.iter().cloned())
+            .collect();
+        let mut rejected = vec![];
+        let mut kept: Vec<_> = prices.iter().filter(|t| t.last_price.is_some()).collect();
+        if kept.len() >= MIN_PRICES_FOR_REJECTION {
+            let median_price = median(kept.iter().flat_map(|t| t.last_price)).unwrap();
+            if !median_price.is_zero() {
+                // Floored so that identical quotes don't turn every other
+                // price into an outlier.
+                let mad = median(kept.iter().flat_map(|t| t.last_price).map(|p| {
+                    p.checked_sub(median_price)
+                        .map_or(Decimal::MAX, |d| d.abs())
+                }))
+                .unwrap()
+                .max(median_price.abs() / Decimal::from(10000));
+                kept.retain(
+                    |t| match self.outlier(t.last_price.unwrap(), median_price, mad) {
+                        Some(reason) => {
+                            rejected.push(format!("{} ({})", t.sources.join(","), reason));
+                            false
+                        }
+                        None => true,
+                    },
+                );
+            }
+        }
+        // Counted before priority picks one, which would otherwise never meet
+        // a quorum above one.
+        let usable = kept.len();
+        if self.rules.strategy == Strategy::Priority {
+            kept.truncate(1);
+        }
+        let mut last_price = self.combine(&kept, |t| t.last_price);
+        let mut prev_price = self.combine(&kept, |t| t.prev_price);
+        if usable < self.rules.quorum {
+            errors.push(SourceError::new(
+                self.name(),
+                ErrorKind::Quorum,
+                format!(
+                    "Only {} of {} sources usable, {} required",
+                    usable,
+                    self.sources.len(),
+                    self.rules.quorum
+                ),
+            ));
+            last_price = None;
+            prev_price = None;
+        }
         TickerData {
-            last_price: median(last_price_vec.iter().cloned()),
-            prev_price: median(prev_price_vec.iter().cloned()),
+            last_price,
+            prev_price,
             insufficient_data: self.sources.is_empty()
-                || (last_price_vec.len() < self.sources.len() && last_price_vec.len() < 3),
-            errors: prices
+                || last_price.is_none()
+                || (self.rules.strategy != Strategy::Priority
+                    && kept.len() < self.sources.len()
+                    && kept.len() < 3),
+            volume: kept
                 .iter()
-                .flat_map(|t| t.errors.iter().cloned())
+                .flat_map(|t| t.volume)
+                .reduce(Decimal::saturating_add),
+            high: self.combine(&kept, |t| t.high),
+            low: self.combine(&kept, |t| t.low),
+            // The best quote across venues.
+            bid: kept.iter().flat_map(|t| t.bid).max(),
+            ask: kept.iter().flat_map(|t| t.ask).min(),
+            timestamp: kept.iter().flat_map(|t| t.timestamp).max(),
+            latency: None,
+            fetched_at: None,
+            sources: kept
+                .iter()
+                .flat_map(|t| t.sources.iter().cloned())
                 .collect(),
+            rejected,
+            errors,
+            degraded: prices.iter().any(|t| t.degraded || !t.errors.is_empty()),
         }
     }
 }
 
+#[async_trait]
+impl TickerDataSource for Aggregator {
+    async fn get_ticker_data(&self) -> TickerData {
+        let prices = join_all(self.sources.iter().map(|s| s.get_ticker_data())).await;
+        self.aggregate(&prices)
+    }
+
+    fn name(&self) -> String {
+        self.sources
+            .iter()
+            .map(|s| s.name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    async fn get_breakdown(&self) -> (TickerData, Vec<(String, TickerData)>) {
+        let prices = join_all(self.sources.iter().map(|s| s.get_ticker_data())).await;
+        let ticker_data = self.aggregate(&prices);
+        let names = self.sources.iter().map(|s| s.name());
+        (ticker_data, names.zip(prices).collect())
+    }
+}
+
+/// The mean of the values weighted by the second of each pair, or `None` if
+/// there's no weight or the sums overflow.
+fn weighted_mean(weighted: &[(Decimal, Decimal)]) -> Option<Decimal> {
+    let mut sum = Decimal::ZERO;
+    let mut total = Decimal::ZERO;
+    for &(value, weight) in weighted {
+        sum = sum.checked_add(value.checked_mul(weight)?)?;
+        total = total.checked_add(weight)?;
+    }
+    if total.is_zero() {
+        return None;
+    }
+    sum.checked_div(total)
+}
+
 fn median(data: impl Iterator<Item = Decimal>) -> Option<Decimal> {
     let mut data: Vec<_> = data.collect();
     data.sort();
@@ -41,8 +243,303 @@ fn median(data: impl Iterator<Item = Decimal>) -> Option<Decimal> {
         return None;
     }
     Some(if size % 2 == 0 {
-        (data[size / 2 - 1] + data[size / 2]) / Decimal::from_i32(2).unwrap()
+        let (low, high) = (data[size / 2 - 1], data[size / 2]);
+        let two = Decimal::from_i32(2).unwrap();
+        match low.checked_add(high) {
+            Some(sum) => sum / two,
+            // Only when both are large and of the same sign.
+            None => low + (high - low) / two,
+        }
     } else {
         data[size / 2]
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{AggregationRules, Aggregator, Strategy};
+    use crate::datasources::{ErrorKind, SourceError, TickerData, TickerDataSource};
+
+    /// A source that always answers with the same data.
+    struct Fixed(String, TickerData);
+
+    #[async_trait]
+    impl TickerDataSource for Fixed {
+        async fn get_ticker_data(&self) -> TickerData {
+            self.1.clone()
+        }
+
+        fn name(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    fn source(
+        name: &str,
+        price: Decimal,
+        volume: Option<Decimal>,
+    ) -> Box<dyn TickerDataSource + Sync> {
+        Box::new(Fixed(
+            name.to_owned(),
+            TickerData {
+                last_price: Some(price),
+                volume,
+                sources: vec![name.to_owned()],
+                ..Default::default()
+            },
+        ))
+    }
+
+    fn failed(name: &str) -> Box<dyn TickerDataSource + Sync> {
+        let error = SourceError::new(name.to_owned(), ErrorKind::Timeout, "timed out".to_owned());
+        Box::new(Fixed(name.to_owned(), TickerData::error(error)))
+    }
+
+    fn aggregator(prices: &[Decimal], rules: AggregationRules) -> Aggregator {
+        let sources = prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| source(&format!("s{}", i), price, None))
+            .collect();
+        Aggregator::new(sources, rules)
+    }
+
+    #[tokio::test]
+    async fn rejects_outlier() {
+        let rules = AggregationRules {
+            max_deviation_percent: Some(dec!(5)),
+            ..Default::default()
+        };
+        let ticker_data = aggregator(&[dec!(100), dec!(101), dec!(99), dec!(150)], rules)
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert_eq!(ticker_data.sources, ["s0", "s1", "s2"]);
+        assert_eq!(ticker_data.rejected, ["s3 (49.25% from median)"]);
+        assert!(!ticker_data.insufficient_data);
+    }
+
+    #[tokio::test]
+    async fn rejects_outlier_by_mads() {
+        let rules = AggregationRules {
+            max_deviation_mads: Some(dec!(3)),
+            ..Default::default()
+        };
+        let ticker_data = aggregator(&[dec!(100), dec!(102), dec!(98), dec!(120)], rules)
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.sources, ["s0", "s1", "s2"]);
+        assert_eq!(ticker_data.rejected, ["s3 (9.5 MADs from median)"]);
+    }
+
+    #[tokio::test]
+    async fn identical_quotes_keep_close_prices() {
+        let rules = AggregationRules {
+            max_deviation_mads: Some(dec!(3)),
+            ..Default::default()
+        };
+        let ticker_data = aggregator(&[dec!(100), dec!(100), dec!(100), dec!(100.02)], rules)
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert!(
+            ticker_data.rejected.is_empty(),
+            "{:?}",
+            ticker_data.rejected
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_outlier_from_tiny_median() {
+        // The MAD floor rounds to zero here, and the outlier's deviation is
+        // too large to express as a percentage.
+        let tiny = Decimal::new(1, 28);
+        let huge = dec!(7000000000000000000000000000);
+        let by_percent = AggregationRules {
+            max_deviation_percent: Some(dec!(5)),
+            ..Default::default()
+        };
+        let by_mads = AggregationRules {
+            max_deviation_mads: Some(dec!(3)),
+            ..Default::default()
+        };
+        for rules in [by_percent, by_mads] {
+            let ticker_data = aggregator(&[tiny, tiny, tiny, huge], rules)
+                .get_ticker_data()
+                .await;
+            assert_eq!(ticker_data.last_price, Some(tiny));
+            assert_eq!(ticker_data.rejected, ["s3 (too far from median)"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn too_few_prices_to_reject() {
+        let rules = AggregationRules {
+            max_deviation_percent: Some(dec!(5)),
+            max_deviation_mads: Some(dec!(3)),
+            ..Default::default()
+        };
+        let ticker_data = aggregator(&[dec!(100), dec!(200)], rules)
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(150)));
+        assert!(ticker_data.rejected.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quorum_not_met() {
+        let rules = AggregationRules {
+            quorum: 3,
+            ..Default::default()
+        };
+        let sources = vec![
+            source("s0", dec!(100), None),
+            source("s1", dec!(101), None),
+            failed("s2"),
+        ];
+        let ticker_data = Aggregator::new(sources, rules).get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, None);
+        assert!(ticker_data.insufficient_data);
+        let kinds: Vec<_> = ticker_data.errors.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, [ErrorKind::Timeout, ErrorKind::Quorum]);
+        assert_eq!(
+            ticker_data.errors[1].detail,
+            "Only 2 of 3 sources usable, 3 required"
+        );
+    }
+
+    #[tokio::test]
+    async fn median_by_default() {
+        let ticker_data = aggregator(&[dec!(3), dec!(1), dec!(2)], Default::default())
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(2)));
+    }
+
+    fn rules(strategy: Strategy) -> AggregationRules {
+        AggregationRules {
+            strategy,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn volume_weighted() {
+        let sources = vec![
+            source("s0", dec!(100), Some(dec!(3))),
+            source("s1", dec!(200), Some(dec!(1))),
+            source("s2", dec!(1000), None),
+            source("s3", dec!(1000), Some(dec!(0))),
+        ];
+        let ticker_data = Aggregator::new(sources, rules(Strategy::VolumeWeighted))
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(125)));
+        assert_eq!(ticker_data.volume, Some(dec!(4)));
+    }
+
+    #[tokio::test]
+    async fn volume_weighted_without_volume() {
+        let ticker_data = aggregator(
+            &[dec!(100), dec!(200), dec!(400)],
+            rules(Strategy::VolumeWeighted),
+        )
+        .get_ticker_data()
+        .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(200)));
+        assert_eq!(ticker_data.volume, None);
+    }
+
+    #[tokio::test]
+    async fn volume_weighted_overflow() {
+        let sources = vec![
+            source("s0", Decimal::MAX, Some(Decimal::MAX)),
+            source("s1", dec!(1), Some(dec!(1))),
+        ];
+        let ticker_data = Aggregator::new(sources, rules(Strategy::VolumeWeighted))
+            .get_ticker_data()
+            .await;
+        assert_eq!(
+            ticker_data.last_price,
+            Some(Decimal::MAX / dec!(2) + dec!(0.5))
+        );
+        assert_eq!(ticker_data.volume, Some(Decimal::MAX));
+    }
+
+    #[tokio::test]
+    async fn trimmed_mean() {
+        let ticker_data = aggregator(
+            &[dec!(1), dec!(100), dec!(102), dec!(1000)],
+            rules(Strategy::TrimmedMean),
+        )
+        .get_ticker_data()
+        .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(101)));
+        let ticker_data = aggregator(&[dec!(100), dec!(102)], rules(Strategy::TrimmedMean))
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(101)));
+    }
+
+    #[tokio::test]
+    async fn trimmed_mean_overflow() {
+        let ticker_data = aggregator(
+            &[Decimal::MAX, Decimal::MAX, Decimal::MAX],
+            rules(Strategy::TrimmedMean),
+        )
+        .get_ticker_data()
+        .await;
+        assert_eq!(ticker_data.last_price, Some(Decimal::MAX));
+        let ticker_data = aggregator(&[Decimal::MAX, Decimal::MAX], rules(Strategy::TrimmedMean))
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(Decimal::MAX));
+    }
+
+    #[tokio::test]
+    async fn priority() {
+        let sources = vec![
+            failed("s0"),
+            source("s1", dec!(100), None),
+            source("s2", dec!(200), None),
+        ];
+        let ticker_data = Aggregator::new(sources, rules(Strategy::Priority))
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert_eq!(ticker_data.sources, ["s1"]);
+        assert!(!ticker_data.insufficient_data);
+        assert!(ticker_data.degraded);
+    }
+
+    #[tokio::test]
+    async fn priority_with_quorum() {
+        let rules = AggregationRules {
+            strategy: Strategy::Priority,
+            quorum: 2,
+            ..Default::default()
+        };
+        let sources = vec![
+            failed("s0"),
+            source("s1", dec!(100), None),
+            source("s2", dec!(200), None),
+        ];
+        let ticker_data = Aggregator::new(sources, rules.clone())
+            .get_ticker_data()
+            .await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert_eq!(ticker_data.sources, ["s1"]);
+
+        let sources = vec![failed("s0"), source("s1", dec!(100), None), failed("s2")];
+        let ticker_data = Aggregator::new(sources, rules).get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, None);
+        assert_eq!(
+            ticker_data.errors.last().unwrap().detail,
+            "Only 1 of 3 sources usable, 2 required"
+        );
+    }
+}
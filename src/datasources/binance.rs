@@ -1,89 +1,86 @@
 use std::{
-    str::FromStr,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use log::info;
 use reqwest::Client;
-use rust_decimal::Decimal;
 use serde_json::Value as JsonValue;
-use tokio::sync::Mutex;
 
-use super::datasource::{TickerData, TickerDataSource};
+use super::datasource::{check_status, decimal, required_decimal, TickerData, TickerDataSource};
+use super::error::{fail, ErrorKind};
+
+/// Binance's error code for symbols it doesn't list.
+const INVALID_SYMBOL: i64 = -1121;
 
 pub struct BinanceTickerDataSource {
     client: Arc<Client>,
+    base_url: String,
     ticker: String,
-    last_check_res: Mutex<Option<(Instant, TickerData)>>,
 }
 
 impl BinanceTickerDataSource {
-    pub fn new(client: Arc<Client>, ticker: String) -> BinanceTickerDataSource {
+    pub fn new(client: Arc<Client>, base_url: String, ticker: String) -> BinanceTickerDataSource {
         BinanceTickerDataSource {
             client,
+            base_url,
             ticker,
-            last_check_res: Mutex::new(None),
         }
     }
 
-    async fn run_query(&self) -> Result<(Decimal, Decimal)> {
-        let resp_payload = self
+    async fn run_query(&self) -> Result<TickerData> {
+        let response = self
             .client
             .get(format!(
-                "https://api-gcp.binance.com/api/v3/ticker/24hr?symbol={}&type=MINI",
-                &self.ticker
+                "{}/api/v3/ticker/24hr?symbol={}",
+                &self.base_url, &self.ticker
             ))
             .send()
             .await?;
-        info!("Binance response code: {}", resp_payload.status());
-        let response: JsonValue = resp_payload.json().await?;
+        info!("Binance response code: {}", response.status());
+        let response: JsonValue = check_status(response)?.json().await?;
         info!("Binance: {} {}", &self.ticker, response);
         if response["msg"] != JsonValue::Null {
-            return Err(anyhow!("Binance: {}", response["msg"]));
+            let kind = if response["code"] == INVALID_SYMBOL {
+                ErrorKind::UnknownSymbol
+            } else {
+                ErrorKind::Exchange
+            };
+            return Err(fail(kind, format!("Binance: {}", response["msg"])));
         }
-        let last = Decimal::from_str(
-            response["lastPrice"]
-                .as_str()
-                .ok_or(anyhow!("Failed to parse Binance response"))?,
-        )?;
-        let open = Decimal::from_str(
-            response["openPrice"]
-                .as_str()
-                .ok_or(anyhow!("Failed to parse Binance response"))?,
-        )?;
-        Ok((last, open))
+        Ok(TickerData {
+            last_price: Some(required_decimal(&response["lastPrice"], "Binance")?),
+            prev_price: Some(required_decimal(&response["openPrice"], "Binance")?),
+            insufficient_data: false,
+            volume: decimal(&response["volume"]),
+            high: decimal(&response["highPrice"]),
+            low: decimal(&response["lowPrice"]),
+            bid: decimal(&response["bidPrice"]),
+            ask: decimal(&response["askPrice"]),
+            timestamp: response["closeTime"]
+                .as_u64()
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+            latency: None,
+            fetched_at: None,
+            sources: vec![self.name()],
+            rejected: vec![],
+            errors: vec![],
+            degraded: false,
+        })
     }
 }
 
 #[async_trait]
 impl TickerDataSource for BinanceTickerDataSource {
     async fn get_ticker_data(&self) -> TickerData {
-        let mut last_check_res = self.last_check_res.lock().await;
-        if let Some((ref time, ref ticker_data)) = *last_check_res {
-            if time.elapsed() < Duration::from_secs(5) {
-                return ticker_data.clone();
-            }
-        }
-        match self.run_query().await {
-            Ok((last_price, prev_price)) => {
-                let ticker_data = TickerData {
-                    last_price: Some(last_price),
-                    prev_price: Some(prev_price),
-                    insufficient_data: false,
-                    errors: vec![],
-                };
-                *last_check_res = Some((Instant::now(), ticker_data.clone()));
-                ticker_data
-            }
-            Err(e) => TickerData {
-                last_price: None,
-                prev_price: None,
-                insufficient_data: true,
-                errors: vec![e.to_string()],
-            },
-        }
+        self.run_query()
+            .await
+            .unwrap_or_else(|e| TickerData::failed(self.name(), e))
+    }
+
+    fn name(&self) -> String {
+        format!("binance:{}", self.ticker)
     }
 }
@@ -0,0 +1,282 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use super::datasource::{TickerData, TickerDataSource};
+use crate::metrics::METRICS;
+
+/// How long a `CachedSource` keeps results.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// Results younger than this are served without asking upstream.
+    pub ttl: Duration,
+    /// For this long past `ttl` the old result is still served while a
+    /// refresh runs in the background.
+    pub stale: Duration,
+    /// Failed queries are remembered for this long, so a broken upstream
+    /// isn't hammered.
+    pub error_ttl: Duration,
+}
+
+impl Default for CachePolicy {
+    fn default() -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_secs(5),
+            stale: Duration::from_secs(10),
+            error_ttl: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Caches another source's results. Concurrent callers that miss the cache
+/// share a single upstream request.
+pub struct CachedSource {
+    shared: Arc<Shared>,
+    /// Source label of the cache metrics.
+    label: String,
+}
+
+struct Shared {
+    source: Box<dyn TickerDataSource + Sync>,
+    policy: CachePolicy,
+    entry: Mutex<Option<(Instant, TickerData)>>,
+    /// Held while querying upstream; callers queue on it rather than
+    /// sending their own request.
+    refresh: tokio::sync::Mutex<()>,
+    refreshing_in_background: AtomicBool,
+}
+
+impl CachedSource {
+    pub fn new(
+        source: Box<dyn TickerDataSource + Sync>,
+        policy: CachePolicy,
+        label: String,
+    ) -> CachedSource {
+        CachedSource {
+            label,
+            shared: Arc::new(Shared {
+                source,
+                policy,
+                entry: Mutex::new(None),
+                refresh: tokio::sync::Mutex::new(()),
+                refreshing_in_background: AtomicBool::new(false),
+            }),
+        }
+    }
+}
+
+impl Shared {
+    /// The cached result if it may be served, and whether it is stale.
+    fn cached(&self) -> Option<(TickerData, bool)> {
+        let entry = self.entry.lock().unwrap();
+        let (time, ticker_data) = entry.as_ref()?;
+        let age = time.elapsed();
+        if !ticker_data.errors.is_empty() {
+            return if age < self.policy.error_ttl {
+                Some((ticker_data.clone(), false))
+            } else {
+                None
+            };
+        }
+        if age < self.policy.ttl {
+            Some((ticker_data.clone(), false))
+        } else if age < self.policy.ttl + self.policy.stale {
+            Some((ticker_data.clone(), true))
+        } else {
+            None
+        }
+    }
+
+    async fn refresh(&self) -> TickerData {
+        let _refresh = self.refresh.lock().await;
+        // Whoever held the lock before may have just refreshed.
+        if let Some((ticker_data, false)) = self.cached() {
+            return ticker_data;
+        }
+        let start = Instant::now();
+        let mut ticker_data = self.source.get_ticker_data().await;
+        ticker_data.latency = Some(start.elapsed());
+        ticker_data.fetched_at = Some(Instant::now());
+        *self.entry.lock().unwrap() = Some((Instant::now(), ticker_data.clone()));
+        ticker_data
+    }
+}
+
+#[async_trait]
+impl TickerDataSource for CachedSource {
+    async fn get_ticker_data(&self) -> TickerData {
+        let cached = self.shared.cached();
+        let result = match cached {
+            Some((_, false)) => "hit",
+            Some((_, true)) => "stale",
+            None => "miss",
+        };
+        METRICS
+            .cache_lookups
+            .with_label_values(&[&self.label, result])
+            .inc();
+        match cached {
+            Some((ticker_data, false)) => ticker_data,
+            Some((ticker_data, true)) => {
+                if !self
+                    .shared
+                    .refreshing_in_background
+                    .swap(true, Ordering::AcqRel)
+                {
+                    let shared = self.shared.clone();
+                    tokio::spawn(async move {
+                        shared.refresh().await;
+                        shared
+                            .refreshing_in_background
+                            .store(false, Ordering::Release);
+                    });
+                }
+                ticker_data
+            }
+            None => self.shared.refresh().await,
+        }
+    }
+
+    fn name(&self) -> String {
+        self.shared.source.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use futures::future::join_all;
+    use rust_decimal::Decimal;
+
+    use super::{CachePolicy, CachedSource};
+    use crate::datasources::{ErrorKind, SourceError, TickerData, TickerDataSource};
+
+    /// Answers with the number of the query as the price, after `delay`.
+    /// The first `failures` queries time out instead.
+    struct Counting {
+        calls: Arc<AtomicUsize>,
+        delay: Duration,
+        failures: usize,
+    }
+
+    #[async_trait]
+    impl TickerDataSource for Counting {
+        async fn get_ticker_data(&self) -> TickerData {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(self.delay).await;
+            if call <= self.failures {
+                return TickerData::error(SourceError::new(
+                    self.name(),
+                    ErrorKind::Timeout,
+                    "timed out".to_owned(),
+                ));
+            }
+            TickerData {
+                last_price: Some(Decimal::from(call)),
+                ..Default::default()
+            }
+        }
+
+        fn name(&self) -> String {
+            "counting".to_owned()
+        }
+    }
+
+    fn cached(
+        policy: CachePolicy,
+        delay: Duration,
+        failures: usize,
+    ) -> (CachedSource, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let source = Counting {
+            calls: calls.clone(),
+            delay,
+            failures,
+        };
+        let cached = CachedSource::new(Box::new(source), policy, "counting".to_owned());
+        (cached, calls)
+    }
+
+    fn policy(ttl_ms: u64, stale_ms: u64, error_ttl_ms: u64) -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_millis(ttl_ms),
+            stale: Duration::from_millis(stale_ms),
+            error_ttl: Duration::from_millis(error_ttl_ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_hits_within_ttl() {
+        let (source, calls) = cached(policy(1000, 0, 0), Duration::ZERO, 0);
+        let first = source.get_ticker_data().await;
+        assert_eq!(first.last_price, Some(Decimal::from(1)));
+        assert!(first.fetched_at.is_some());
+        let second = source.get_ticker_data().await;
+        assert_eq!(second.last_price, Some(Decimal::from(1)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn misses_after_stale_window() {
+        let (source, calls) = cached(policy(20, 20, 0), Duration::ZERO, 0);
+        source.get_ticker_data().await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, Some(Decimal::from(2)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_while_refreshing_once() {
+        let (source, calls) = cached(policy(100, 1000, 0), Duration::from_millis(50), 0);
+        source.get_ticker_data().await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        for _ in 0..3 {
+            let ticker_data = source.get_ticker_data().await;
+            assert_eq!(ticker_data.last_price, Some(Decimal::from(1)));
+        }
+        // Long enough for the refresh, short of it going stale again.
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, Some(Decimal::from(2)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn remembers_errors_for_error_ttl() {
+        let (source, calls) = cached(policy(1000, 0, 50), Duration::ZERO, 1);
+        for _ in 0..2 {
+            let ticker_data = source.get_ticker_data().await;
+            assert_eq!(ticker_data.errors[0].kind, ErrorKind::Timeout);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, Some(Decimal::from(2)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_query() {
+        let (source, calls) = cached(policy(1000, 0, 0), Duration::from_millis(50), 0);
+        let results = join_all((0..5).map(|_| source.get_ticker_data())).await;
+        for ticker_data in results {
+            assert_eq!(ticker_data.last_price, Some(Decimal::from(1)));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,88 +1,93 @@
-use std::{
-    str::FromStr,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{sync::Arc, time::SystemTime};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_trait::async_trait;
-use log::info;
+use chrono::DateTime;
+use log::{info, warn};
 use reqwest::Client;
-use rust_decimal::Decimal;
 use serde_json::Value as JsonValue;
-use tokio::sync::Mutex;
 
-use super::datasource::{TickerData, TickerDataSource};
+use super::datasource::{check_status, decimal, required_decimal, TickerData, TickerDataSource};
+use super::error::{fail, ErrorKind};
 
 pub struct CoinbaseTickerDataSource {
     client: Arc<Client>,
+    base_url: String,
     ticker: String,
-    last_check_res: Mutex<Option<(Instant, TickerData)>>,
 }
 
 impl CoinbaseTickerDataSource {
-    pub fn new(client: Arc<Client>, ticker: String) -> CoinbaseTickerDataSource {
+    pub fn new(client: Arc<Client>, base_url: String, ticker: String) -> CoinbaseTickerDataSource {
         CoinbaseTickerDataSource {
             client,
+            base_url,
             ticker,
-            last_check_res: Mutex::new(None),
         }
     }
 
-    async fn run_query(&self) -> Result<(Decimal, Decimal)> {
-        let resp_payload = self
+    async fn get(&self, endpoint: &str) -> Result<JsonValue> {
+        let response = self
             .client
             .get(format!(
-                "https://api.exchange.coinbase.com/products/{}/stats",
-                &self.ticker
+                "{}/products/{}/{}",
+                &self.base_url, &self.ticker, endpoint
             ))
             .send()
             .await?;
-        let response: JsonValue = resp_payload.json().await?;
-        info!("Coinbase: {} {}", &self.ticker, response);
+        let response: JsonValue = check_status(response)?.json().await?;
+        info!("Coinbase: {} {} {}", &self.ticker, endpoint, response);
         if response["message"] != JsonValue::Null {
-            return Err(anyhow!("Coinbase: {}", response["message"]));
+            let kind = if response["message"] == "NotFound" {
+                ErrorKind::UnknownSymbol
+            } else {
+                ErrorKind::Exchange
+            };
+            return Err(fail(kind, format!("Coinbase: {}", response["message"])));
         }
-        let last = Decimal::from_str(
-            response["last"]
-                .as_str()
-                .ok_or(anyhow!("Failed to parse Coinbase response"))?,
-        )?;
-        let open = Decimal::from_str(
-            response["open"]
+        Ok(response)
+    }
+
+    async fn run_query(&self) -> Result<TickerData> {
+        // Stats carry the price and the day's range; the ticker only adds the
+        // book and time, so the price doesn't depend on it.
+        let (stats, ticker) = tokio::join!(self.get("stats"), self.get("ticker"));
+        let stats = stats?;
+        let ticker = ticker.unwrap_or_else(|e| {
+            warn!("Coinbase: {} ticker: {}", &self.ticker, e);
+            JsonValue::Null
+        });
+        Ok(TickerData {
+            last_price: Some(required_decimal(&stats["last"], "Coinbase")?),
+            prev_price: Some(required_decimal(&stats["open"], "Coinbase")?),
+            insufficient_data: false,
+            volume: decimal(&stats["volume"]),
+            high: decimal(&stats["high"]),
+            low: decimal(&stats["low"]),
+            bid: decimal(&ticker["bid"]),
+            ask: decimal(&ticker["ask"]),
+            timestamp: ticker["time"]
                 .as_str()
-                .ok_or(anyhow!("Failed to parse Coinbase response"))?,
-        )?;
-        Ok((last, open))
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(SystemTime::from),
+            latency: None,
+            fetched_at: None,
+            sources: vec![self.name()],
+            rejected: vec![],
+            errors: vec![],
+            degraded: false,
+        })
     }
 }
 
 #[async_trait]
 impl TickerDataSource for CoinbaseTickerDataSource {
     async fn get_ticker_data(&self) -> TickerData {
-        let mut last_check_res = self.last_check_res.lock().await;
-        if let Some((ref time, ref ticker_data)) = *last_check_res {
-            if time.elapsed() < Duration::from_secs(5) {
-                return ticker_data.clone();
-            }
-        }
-        match self.run_query().await {
-            Ok((last_price, prev_price)) => {
-                let ticker_data = TickerData {
-                    last_price: Some(last_price),
-                    prev_price: Some(prev_price),
-                    insufficient_data: false,
-                    errors: vec![],
-                };
-                *last_check_res = Some((Instant::now(), ticker_data.clone()));
-                ticker_data
-            }
-            Err(e) => TickerData {
-                last_price: None,
-                prev_price: None,
-                insufficient_data: true,
-                errors: vec![e.to_string()],
-            },
-        }
+        self.run_query()
+            .await
+            .unwrap_or_else(|e| TickerData::failed(self.name(), e))
+    }
+
+    fn name(&self) -> String {
+        format!("coinbase:{}", self.ticker)
     }
 }
@@ -1,15 +1,101 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
 use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
 use rust_decimal::Decimal;
+use serde_json::Value as JsonValue;
+
+use super::error::{fail, ErrorKind, SourceError};
 
 #[async_trait]
 pub trait TickerDataSource: Sync + Send {
     async fn get_ticker_data(&self) -> TickerData;
+
+    /// Identifies the source in messages, e.g. `binance:BTCUSDT`.
+    fn name(&self) -> String;
+
+    /// The result along with the named results it was combined from, for
+    /// sources that combine others; otherwise just the result itself.
+    async fn get_breakdown(&self) -> (TickerData, Vec<(String, TickerData)>) {
+        let ticker_data = self.get_ticker_data().await;
+        (ticker_data.clone(), vec![(self.name(), ticker_data)])
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct TickerData {
     pub last_price: Option<Decimal>,
     pub prev_price: Option<Decimal>,
     pub insufficient_data: bool,
-    pub errors: Vec<String>,
+    /// Traded volume over the last 24 hours, in the base asset.
+    pub volume: Option<Decimal>,
+    /// Highest and lowest price over the source's trading day or last 24
+    /// hours, whichever it reports.
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub bid: Option<Decimal>,
+    pub ask: Option<Decimal>,
+    /// When the exchange says the data was taken, if it says.
+    pub timestamp: Option<SystemTime>,
+    /// How long the upstream request took, and when it completed.
+    pub latency: Option<Duration>,
+    pub fetched_at: Option<Instant>,
+    /// Sources whose price went into `last_price`, e.g. `binance:BTCUSDT`.
+    pub sources: Vec<String>,
+    /// Sources left out of `last_price` as outliers, with the reason.
+    pub rejected: Vec<String>,
+    pub errors: Vec<SourceError>,
+    /// Some of the sources this was combined from failed or were skipped.
+    pub degraded: bool,
+}
+
+impl TickerData {
+    /// The result of a failed query.
+    pub fn error(error: SourceError) -> TickerData {
+        TickerData {
+            insufficient_data: true,
+            errors: vec![error],
+            ..Default::default()
+        }
+    }
+
+    /// The result of a failed query of `source`, with the error classified.
+    pub fn failed(source: String, error: anyhow::Error) -> TickerData {
+        TickerData::error(SourceError::classify(source, &error))
+    }
+
+    /// Whether every error is likely to clear up if the query is repeated.
+    pub fn is_transient(&self) -> bool {
+        !self.errors.is_empty() && self.errors.iter().all(|e| e.kind.is_transient())
+    }
+}
+
+/// Fails on statuses that are worth retrying. Other error statuses come with
+/// a body explaining the problem, which the caller reports instead.
+pub(super) fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        Ok(response.error_for_status()?)
+    } else {
+        Ok(response)
+    }
+}
+
+/// Reads a decimal that exchanges send as a JSON string.
+pub(super) fn decimal(value: &JsonValue) -> Option<Decimal> {
+    Decimal::from_str(value.as_str()?).ok()
+}
+
+/// Like `decimal`, for fields the response can't do without.
+pub(super) fn required_decimal(value: &JsonValue, exchange: &str) -> Result<Decimal> {
+    decimal(value).ok_or_else(|| {
+        fail(
+            ErrorKind::Parse,
+            format!("Failed to parse {} response", exchange),
+        )
+    })
 }
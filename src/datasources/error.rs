@@ -0,0 +1,190 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// What went wrong with a source, as far as retrying and reporting care.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The request didn't get through, e.g. DNS or connection failures.
+    Network,
+    Timeout,
+    /// The upstream answered with an error status and nothing more specific.
+    HttpStatus(u16),
+    /// The exchange reported an error of its own.
+    Exchange,
+    /// The response wasn't what the source expected.
+    Parse,
+    /// The exchange doesn't know the symbol.
+    UnknownSymbol,
+    /// The source was left out after failing repeatedly.
+    Skipped,
+    /// Too few sources were left to make a price from.
+    Quorum,
+    /// A price couldn't be converted to the requested currency.
+    NoExchangeRate,
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether repeating the query soon may well succeed.
+    pub fn is_transient(self) -> bool {
+        match self {
+            ErrorKind::Network | ErrorKind::Timeout => true,
+            ErrorKind::HttpStatus(status) => status >= 500 || status == 429,
+            _ => false,
+        }
+    }
+
+    /// Label for metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Network => "network",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::HttpStatus(_) => "http_status",
+            ErrorKind::Exchange => "exchange",
+            ErrorKind::Parse => "parse",
+            ErrorKind::UnknownSymbol => "unknown_symbol",
+            ErrorKind::Skipped => "skipped",
+            ErrorKind::Quorum => "quorum",
+            ErrorKind::NoExchangeRate => "no_exchange_rate",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+/// Short enough to show in a chat, without any upstream text.
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Network => write!(f, "unreachable"),
+            ErrorKind::Timeout => write!(f, "timed out"),
+            ErrorKind::HttpStatus(status) => write!(f, "HTTP {}", status),
+            ErrorKind::Exchange => write!(f, "exchange error"),
+            ErrorKind::Parse => write!(f, "unexpected response"),
+            ErrorKind::UnknownSymbol => write!(f, "unknown symbol"),
+            ErrorKind::Skipped => write!(f, "skipped after repeated failures"),
+            ErrorKind::Quorum => write!(f, "too few sources"),
+            ErrorKind::NoExchangeRate => write!(f, "no exchange rate"),
+            ErrorKind::Other => write!(f, "failed"),
+        }
+    }
+}
+
+/// A failure of one source. `Display` gives the full detail for logs;
+/// `summary` is what users see.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceError {
+    /// The source's name, e.g. `binance:BTCUSDT`.
+    pub source: String,
+    pub kind: ErrorKind,
+    pub detail: String,
+}
+
+impl SourceError {
+    pub fn new(source: String, kind: ErrorKind, detail: String) -> SourceError {
+        SourceError {
+            source,
+            kind,
+            detail,
+        }
+    }
+
+    /// Classifies an error from querying `source`: errors raised with
+    /// `fail`, then HTTP client errors, then numbers that didn't parse;
+    /// anything else is `Other`.
+    pub fn classify(source: String, error: &anyhow::Error) -> SourceError {
+        let kind = if let Some(failure) = error.downcast_ref::<Failure>() {
+            failure.kind
+        } else if let Some(error) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        {
+            if error.is_timeout() {
+                ErrorKind::Timeout
+            } else if let Some(status) = error.status() {
+                ErrorKind::HttpStatus(status.as_u16())
+            } else if error.is_decode() {
+                ErrorKind::Parse
+            } else {
+                ErrorKind::Network
+            }
+        } else if error.chain().any(|cause| {
+            cause.is::<rust_decimal::Error>()
+                || cause.is::<std::num::ParseIntError>()
+                || cause.is::<std::num::ParseFloatError>()
+        }) {
+            ErrorKind::Parse
+        } else {
+            ErrorKind::Other
+        };
+        SourceError::new(source, kind, format!("{:#}", error))
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{}: {}", self.source, self.kind)
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source, self.detail)
+    }
+}
+
+/// An error a source raises knowing its kind, for `SourceError::classify`.
+#[derive(Debug)]
+struct Failure {
+    kind: ErrorKind,
+    detail: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+pub(super) fn fail(kind: ErrorKind, detail: String) -> anyhow::Error {
+    anyhow::Error::new(Failure { kind, detail })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use anyhow::{anyhow, Context};
+    use rust_decimal::Decimal;
+
+    use super::{fail, ErrorKind, SourceError};
+
+    fn kind(error: anyhow::Error) -> ErrorKind {
+        SourceError::classify("test".to_owned(), &error).kind
+    }
+
+    #[test]
+    fn classifies_failures() {
+        assert_eq!(
+            kind(fail(ErrorKind::UnknownSymbol, "no such pair".to_owned())),
+            ErrorKind::UnknownSymbol
+        );
+        let error = SourceError::classify("kraken:X".to_owned(), &anyhow!("boom"));
+        assert_eq!(error.kind, ErrorKind::Other);
+        assert_eq!(error.to_string(), "kraken:X: boom");
+    }
+
+    #[test]
+    fn classifies_number_errors_as_parse() {
+        let decimal = Decimal::from_str("1.2.3").unwrap_err();
+        assert_eq!(kind(decimal.into()), ErrorKind::Parse);
+        let int = "x".parse::<u64>().unwrap_err();
+        assert_eq!(
+            kind(anyhow::Error::new(int).context("volume")),
+            ErrorKind::Parse
+        );
+        let float = "".parse::<f64>().context("price").unwrap_err();
+        assert_eq!(kind(float), ErrorKind::Parse);
+    }
+}
@@ -1,85 +1,90 @@
 use std::{
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use log::info;
 use reqwest::Client;
 use rust_decimal::{prelude::FromPrimitive, Decimal};
 use serde_json::Value as JsonValue;
-use tokio::sync::Mutex;
 
-use super::datasource::{TickerData, TickerDataSource};
+use super::datasource::{check_status, TickerData, TickerDataSource};
+use super::error::{fail, ErrorKind};
 
 pub struct GoldpriceTickerDataSource {
     client: Arc<Client>,
+    base_url: String,
     metal: String,
     currency: String,
-    last_check_res: Mutex<Option<(Instant, TickerData)>>,
 }
 
 impl GoldpriceTickerDataSource {
-    pub fn new(client: Arc<Client>, metal: String, currency: String) -> GoldpriceTickerDataSource {
+    pub fn new(
+        client: Arc<Client>,
+        base_url: String,
+        metal: String,
+        currency: String,
+    ) -> GoldpriceTickerDataSource {
         GoldpriceTickerDataSource {
             client,
+            base_url,
             metal,
             currency,
-            last_check_res: Mutex::new(None),
         }
     }
 
-    async fn run_query(&self) -> Result<(Decimal, Decimal)> {
-        let response: JsonValue = self
+    async fn run_query(&self) -> Result<TickerData> {
+        let response = self
             .client
-            .get(format!(
-                "https://data-asg.goldprice.org/dbXRates/{}",
-                &self.currency
-            ))
+            .get(format!("{}/dbXRates/{}", &self.base_url, &self.currency))
             .send()
-            .await?
-            .json()
             .await?;
+        let response: JsonValue = check_status(response)?.json().await?;
         info!("Goldprice: {}", response);
-        let last = response["items"][0][self.metal.to_ascii_lowercase() + "Price"]
-            .as_f64()
-            .and_then(Decimal::from_f64)
-            .ok_or(anyhow!("Failed to parse Goldprice response"))?;
-        let prev = response["items"][0][self.metal.to_ascii_lowercase() + "Close"]
-            .as_f64()
-            .and_then(Decimal::from_f64)
-            .ok_or(anyhow!("Failed to parse Goldprice response"))?;
-        Ok((last, prev))
+        let price = |field: &str| {
+            response["items"][0][self.metal.to_ascii_lowercase() + field]
+                .as_f64()
+                .and_then(Decimal::from_f64)
+                .ok_or_else(|| {
+                    fail(
+                        ErrorKind::Parse,
+                        "Failed to parse Goldprice response".to_owned(),
+                    )
+                })
+        };
+        Ok(TickerData {
+            last_price: Some(price("Price")?),
+            prev_price: Some(price("Close")?),
+            insufficient_data: false,
+            volume: None,
+            high: None,
+            low: None,
+            bid: None,
+            ask: None,
+            timestamp: response["ts"]
+                .as_u64()
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+            latency: None,
+            fetched_at: None,
+            sources: vec![self.name()],
+            rejected: vec![],
+            errors: vec![],
+            degraded: false,
+        })
     }
 }
 
 #[async_trait]
 impl TickerDataSource for GoldpriceTickerDataSource {
     async fn get_ticker_data(&self) -> TickerData {
-        let mut last_check_res = self.last_check_res.lock().await;
-        if let Some((ref time, ref ticker_data)) = *last_check_res {
-            if time.elapsed() < Duration::from_secs(5) {
-                return ticker_data.clone();
-            }
-        }
-        match self.run_query().await {
-            Ok((last_price, prev_price)) => {
-                let ticker_data = TickerData {
-                    last_price: Some(last_price),
-                    prev_price: Some(prev_price),
-                    insufficient_data: false,
-                    errors: vec![],
-                };
-                *last_check_res = Some((Instant::now(), ticker_data.clone()));
-                ticker_data
-            }
-            Err(e) => TickerData {
-                last_price: None,
-                prev_price: None,
-                insufficient_data: true,
-                errors: vec![e.to_string()],
-            },
-        }
+        self.run_query()
+            .await
+            .unwrap_or_else(|e| TickerData::failed(self.name(), e))
+    }
+
+    fn name(&self) -> String {
+        format!("goldprice:{}/{}", self.metal, self.currency)
     }
 }
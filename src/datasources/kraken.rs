@@ -1,84 +1,82 @@
-use std::{
-    str::FromStr,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::sync::Arc;
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use log::info;
 use reqwest::Client;
-use rust_decimal::Decimal;
 use serde_json::Value as JsonValue;
-use tokio::sync::Mutex;
 
-use super::datasource::{TickerData, TickerDataSource};
+use super::datasource::{check_status, decimal, required_decimal, TickerData, TickerDataSource};
+use super::error::{fail, ErrorKind};
 
 pub struct KrakenTickerDataSource {
     client: Arc<Client>,
+    base_url: String,
     ticker: String,
-    last_check_res: Mutex<Option<(Instant, TickerData)>>,
 }
 
 impl KrakenTickerDataSource {
-    pub fn new(client: Arc<Client>, ticker: String) -> KrakenTickerDataSource {
+    pub fn new(client: Arc<Client>, base_url: String, ticker: String) -> KrakenTickerDataSource {
         KrakenTickerDataSource {
             client,
+            base_url,
             ticker,
-            last_check_res: Mutex::new(None),
         }
     }
 
-    async fn run_query(&self) -> Result<Decimal> {
-        let response: JsonValue = self
+    async fn run_query(&self) -> Result<TickerData> {
+        let response = self
             .client
             .get(format!(
-                "https://api.kraken.com/0/public/Ticker?pair={}",
-                &self.ticker
+                "{}/0/public/Ticker?pair={}",
+                &self.base_url, &self.ticker
             ))
             .send()
-            .await?
-            .json()
             .await?;
+        let response: JsonValue = check_status(response)?.json().await?;
         info!("Kraken: {} {}", &self.ticker, response);
-        if response["error"][0] != JsonValue::Null {
-            return Err(anyhow!("Kraken: {}", response["error"][0]));
+        if let Some(error) = response["error"][0].as_str() {
+            let kind = if error.starts_with("EQuery:Unknown asset pair") {
+                ErrorKind::UnknownSymbol
+            } else {
+                ErrorKind::Exchange
+            };
+            return Err(fail(kind, format!("Kraken: {}", error)));
         }
-        let last = Decimal::from_str(
-            response["result"][&self.ticker]["c"][0]
-                .as_str()
-                .ok_or(anyhow!("Failed to parse Kraken response"))?,
-        )?;
-        Ok(last)
+        let ticker = &response["result"][&self.ticker];
+        // Ranges and volume are [today, last 24 hours], but there is no
+        // price from 24 hours ago, only today's opening price at 00:00 UTC.
+        // All of them are today's, so the change, range and volume cover the
+        // same window, which is shorter than other exchanges' 24 hours.
+        Ok(TickerData {
+            last_price: Some(required_decimal(&ticker["c"][0], "Kraken")?),
+            prev_price: decimal(&ticker["o"]),
+            insufficient_data: false,
+            volume: decimal(&ticker["v"][0]),
+            high: decimal(&ticker["h"][0]),
+            low: decimal(&ticker["l"][0]),
+            bid: decimal(&ticker["b"][0]),
+            ask: decimal(&ticker["a"][0]),
+            timestamp: None,
+            latency: None,
+            fetched_at: None,
+            sources: vec![self.name()],
+            rejected: vec![],
+            errors: vec![],
+            degraded: false,
+        })
     }
 }
 
 #[async_trait]
 impl TickerDataSource for KrakenTickerDataSource {
     async fn get_ticker_data(&self) -> TickerData {
-        let mut last_check_res = self.last_check_res.lock().await;
-        if let Some((ref time, ref ticker_data)) = *last_check_res {
-            if time.elapsed() < Duration::from_secs(5) {
-                return ticker_data.clone();
-            }
-        }
-        match self.run_query().await {
-            Ok(last_price) => {
-                let ticker_data = TickerData {
-                    last_price: Some(last_price),
-                    prev_price: None,
-                    insufficient_data: false,
-                    errors: vec![],
-                };
-                *last_check_res = Some((Instant::now(), ticker_data.clone()));
-                ticker_data
-            }
-            Err(e) => TickerData {
-                last_price: None,
-                prev_price: None,
-                insufficient_data: true,
-                errors: vec![e.to_string()],
-            },
-        }
+        self.run_query()
+            .await
+            .unwrap_or_else(|e| TickerData::failed(self.name(), e))
+    }
+
+    fn name(&self) -> String {
+        format!("kraken:{}", self.ticker)
     }
 }
@@ -1,15 +1,26 @@
 mod aggregator;
 mod binance;
+mod cached;
 mod coinbase;
 mod datasource;
+mod error;
 mod goldprice;
 mod kraken;
+mod monitored;
+mod resilient;
 mod yfinance;
 
-pub use aggregator::Aggregator;
+pub use aggregator::{AggregationRules, Aggregator, Strategy};
 pub use binance::BinanceTickerDataSource;
+pub use cached::{CachePolicy, CachedSource};
 pub use coinbase::CoinbaseTickerDataSource;
 pub use datasource::{TickerData, TickerDataSource};
+pub use error::{ErrorKind, SourceError};
 pub use goldprice::GoldpriceTickerDataSource;
 pub use kraken::KrakenTickerDataSource;
+pub use monitored::{HealthRegistry, MonitoredSource, SourceHealth};
+pub use resilient::{ResiliencePolicy, ResilientSource};
 pub use yfinance::YahooFinanceTickerDataSource;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use super::datasource::{TickerData, TickerDataSource};
+use super::error::SourceError;
+use crate::metrics::METRICS;
+
+/// Queries the success rate and latency percentiles are taken over.
+const WINDOW: usize = 100;
+
+/// Tracks how each upstream source has been doing, by source name.
+#[derive(Default)]
+pub struct HealthRegistry {
+    sources: Mutex<HashMap<String, Stats>>,
+}
+
+#[derive(Default)]
+struct Stats {
+    queries: u64,
+    failures: u64,
+    last_success: Option<SystemTime>,
+    last_error: Option<(SystemTime, SourceError)>,
+    /// Failed queries since startup by the kind of their first error.
+    failures_by_kind: BTreeMap<&'static str, u64>,
+    /// Whether each recent query succeeded, and how long it took.
+    recent: VecDeque<(bool, Duration)>,
+}
+
+/// How one source has been doing.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceHealth {
+    pub name: String,
+    /// Queries and failures since startup.
+    pub queries: u64,
+    pub failures: u64,
+    pub failures_by_kind: BTreeMap<&'static str, u64>,
+    /// Fraction of the last 100 queries that succeeded.
+    pub success_rate: f64,
+    pub p50_latency_ms: Option<u64>,
+    pub p95_latency_ms: Option<u64>,
+    /// Unix seconds.
+    pub last_success: Option<u64>,
+    pub last_error: Option<SourceError>,
+    pub last_error_at: Option<u64>,
+}
+
+impl HealthRegistry {
+    pub fn new() -> HealthRegistry {
+        HealthRegistry::default()
+    }
+
+    /// Drops the sources for which `keep` is false.
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        self.sources.lock().unwrap().retain(|name, _| keep(name));
+    }
+
+    fn record(&self, name: &str, ticker_data: &TickerData, latency: Duration) {
+        let mut sources = self.sources.lock().unwrap();
+        let stats = sources.entry(name.to_owned()).or_default();
+        let ok = ticker_data.errors.is_empty();
+        stats.queries += 1;
+        if ok {
+            stats.last_success = Some(SystemTime::now());
+        } else {
+            stats.failures += 1;
+            let error = &ticker_data.errors[0];
+            *stats.failures_by_kind.entry(error.kind.name()).or_default() += 1;
+            stats.last_error = Some((SystemTime::now(), error.clone()));
+        }
+        if stats.recent.len() == WINDOW {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back((ok, latency));
+    }
+
+    /// Every source queried so far, by name.
+    pub fn report(&self) -> Vec<SourceHealth> {
+        let sources = self.sources.lock().unwrap();
+        let mut report: Vec<_> = sources
+            .iter()
+            .map(|(name, stats)| {
+                let mut latencies: Vec<_> = stats.recent.iter().map(|(_, l)| *l).collect();
+                latencies.sort();
+                let percentile = |p: usize| {
+                    latencies
+                        .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+                        .map(|latency| latency.as_millis() as u64)
+                };
+                let successes = stats.recent.iter().filter(|(ok, _)| *ok).count();
+                SourceHealth {
+                    name: name.clone(),
+                    queries: stats.queries,
+                    failures: stats.failures,
+                    failures_by_kind: stats.failures_by_kind.clone(),
+                    success_rate: successes as f64 / stats.recent.len().max(1) as f64,
+                    p50_latency_ms: percentile(50),
+                    p95_latency_ms: percentile(95),
+                    last_success: stats.last_success.map(unix),
+                    last_error: stats.last_error.as_ref().map(|(_, e)| e.clone()),
+                    last_error_at: stats.last_error.as_ref().map(|(at, _)| unix(*at)),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| a.name.cmp(&b.name));
+        report
+    }
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Records every query of another source in a `HealthRegistry`, and in the
+/// metrics under `label`.
+pub struct MonitoredSource {
+    source: Box<dyn TickerDataSource + Sync>,
+    health: Arc<HealthRegistry>,
+    label: String,
+}
+
+impl MonitoredSource {
+    pub fn new(
+        source: Box<dyn TickerDataSource + Sync>,
+        health: Arc<HealthRegistry>,
+        label: String,
+    ) -> MonitoredSource {
+        MonitoredSource {
+            source,
+            health,
+            label,
+        }
+    }
+}
+
+#[async_trait]
+impl TickerDataSource for MonitoredSource {
+    async fn get_ticker_data(&self) -> TickerData {
+        let start = Instant::now();
+        let ticker_data = self.source.get_ticker_data().await;
+        let latency = start.elapsed();
+        self.health
+            .record(&self.source.name(), &ticker_data, latency);
+        let label = &self.label;
+        METRICS.source_requests.with_label_values(&[label]).inc();
+        for error in &ticker_data.errors {
+            METRICS
+                .source_errors
+                .with_label_values(&[label, error.kind.name()])
+                .inc();
+        }
+        METRICS
+            .source_latency
+            .with_label_values(&[label])
+            .observe(latency.as_secs_f64());
+        ticker_data
+    }
+
+    fn name(&self) -> String {
+        self.source.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::HealthRegistry;
+    use crate::datasources::{ErrorKind, SourceError, TickerData};
+
+    fn failure(kind: ErrorKind) -> TickerData {
+        TickerData::error(SourceError::new("s".to_owned(), kind, "failed".to_owned()))
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let health = HealthRegistry::new();
+        // Out of order, so they have to be sorted.
+        for ms in (1..=100).rev() {
+            health.record("s", &TickerData::default(), Duration::from_millis(ms));
+        }
+        let report = health.report();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].p50_latency_ms, Some(51));
+        assert_eq!(report[0].p95_latency_ms, Some(96));
+
+        health.record("t", &TickerData::default(), Duration::from_millis(7));
+        let report = health.report();
+        assert_eq!(report[1].name, "t");
+        assert_eq!(report[1].p50_latency_ms, Some(7));
+        assert_eq!(report[1].p95_latency_ms, Some(7));
+    }
+
+    #[test]
+    fn success_rate_over_window() {
+        let health = HealthRegistry::new();
+        for _ in 0..50 {
+            health.record("s", &failure(ErrorKind::Timeout), Duration::ZERO);
+        }
+        health.record("s", &failure(ErrorKind::Parse), Duration::ZERO);
+        let report = health.report();
+        assert_eq!(report[0].success_rate, 0.);
+        assert_eq!(report[0].failures_by_kind["timeout"], 50);
+        assert_eq!(report[0].failures_by_kind["parse"], 1);
+        assert_eq!(
+            report[0].last_error.as_ref().unwrap().kind,
+            ErrorKind::Parse
+        );
+        assert_eq!(report[0].last_success, None);
+
+        // The failures drop out of the last 100 queries, not the totals.
+        for _ in 0..100 {
+            health.record("s", &TickerData::default(), Duration::ZERO);
+        }
+        let report = health.report();
+        assert_eq!(report[0].success_rate, 1.);
+        assert_eq!((report[0].queries, report[0].failures), (151, 51));
+        assert!(report[0].last_success.is_some());
+        assert!(report[0].last_error.is_some());
+    }
+
+    #[test]
+    fn retain() {
+        let health = HealthRegistry::new();
+        for name in ["a", "b", "c"] {
+            health.record(name, &TickerData::default(), Duration::ZERO);
+        }
+        health.retain(|name| name != "b");
+        let names: Vec<_> = health.report().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["a", "c"]);
+    }
+}
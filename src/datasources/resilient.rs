@@ -0,0 +1,318 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use log::{info, warn};
+
+use super::datasource::{TickerData, TickerDataSource};
+use super::error::{ErrorKind, SourceError};
+
+/// Longest delay before a retry, however many came before it.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a `ResilientSource` retries and when it gives up on a source.
+#[derive(Debug, Clone)]
+pub struct ResiliencePolicy {
+    /// Further attempts after a query fails with a transient error.
+    pub retries: u32,
+    /// Delay before the first retry; each further one doubles it, up to
+    /// `MAX_BACKOFF`. The actual delay is picked at random from the upper
+    /// half, so sources failing together don't retry in lockstep.
+    pub backoff: Duration,
+    /// Consecutive failed queries after which the source is skipped.
+    pub failure_threshold: u32,
+    /// How long a source is skipped before it is tried again.
+    pub cooloff: Duration,
+}
+
+impl Default for ResiliencePolicy {
+    fn default() -> ResiliencePolicy {
+        ResiliencePolicy {
+            retries: 2,
+            backoff: Duration::from_millis(250),
+            failure_threshold: 3,
+            cooloff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Retries another source's transient failures, and stops querying it for a
+/// while once it keeps failing.
+pub struct ResilientSource {
+    source: Box<dyn TickerDataSource + Sync>,
+    policy: ResiliencePolicy,
+    breaker: Mutex<Breaker>,
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    /// Set while the source is skipped. Once it passes, the next query is
+    /// let through and decides whether the source is skipped again.
+    open_until: Option<Instant>,
+}
+
+impl ResilientSource {
+    pub fn new(
+        source: Box<dyn TickerDataSource + Sync>,
+        policy: ResiliencePolicy,
+    ) -> ResilientSource {
+        ResilientSource {
+            source,
+            policy,
+            breaker: Mutex::new(Breaker::default()),
+        }
+    }
+
+    /// The delay before retry number `attempt`, counting from zero.
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .policy
+            .backoff
+            .saturating_mul(2u32.checked_pow(attempt).unwrap_or(u32::MAX))
+            .min(MAX_BACKOFF);
+        delay / 2 + delay.mul_f64(fastrand::f64()) / 2
+    }
+
+    async fn query_with_retries(&self) -> TickerData {
+        let mut attempt = 0;
+        loop {
+            let ticker_data = self.source.get_ticker_data().await;
+            if !ticker_data.is_transient() || attempt >= self.policy.retries {
+                return ticker_data;
+            }
+            let delay = self.retry_delay(attempt);
+            info!(
+                "{}: retrying in {}ms after {}",
+                self.source.name(),
+                delay.as_millis(),
+                ticker_data
+                    .errors
+                    .iter()
+                    .map(|e| e.detail.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn record(&self, ticker_data: &TickerData) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if ticker_data.errors.is_empty() {
+            if breaker.open_until.take().is_some() {
+                info!("{}: recovered, querying it again", self.source.name());
+            }
+            breaker.failures = 0;
+            return;
+        }
+        // The exchange answered; it just doesn't list the symbol.
+        if ticker_data
+            .errors
+            .iter()
+            .all(|e| e.kind == ErrorKind::UnknownSymbol)
+        {
+            return;
+        }
+        breaker.failures += 1;
+        if breaker.failures >= self.policy.failure_threshold {
+            warn!(
+                "{}: {} failures in a row, skipping it for {}s",
+                self.source.name(),
+                breaker.failures,
+                self.policy.cooloff.as_secs()
+            );
+            breaker.open_until = Some(Instant::now() + self.policy.cooloff);
+        }
+    }
+}
+
+#[async_trait]
+impl TickerDataSource for ResilientSource {
+    async fn get_ticker_data(&self) -> TickerData {
+        let open_until = self.breaker.lock().unwrap().open_until;
+        if let Some(until) = open_until.filter(|until| *until > Instant::now()) {
+            return TickerData::error(SourceError::new(
+                self.source.name(),
+                ErrorKind::Skipped,
+                format!(
+                    "skipped after repeated failures, retrying in {}s",
+                    (until - Instant::now()).as_secs() + 1
+                ),
+            ));
+        }
+        let ticker_data = self.query_with_retries().await;
+        self.record(&ticker_data);
+        ticker_data
+    }
+
+    fn name(&self) -> String {
+        self.source.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use rust_decimal_macros::dec;
+
+    use super::{ResiliencePolicy, ResilientSource, MAX_BACKOFF};
+    use crate::datasources::{ErrorKind, SourceError, TickerData, TickerDataSource};
+
+    /// Answers with the scripted results in turn, then with a price.
+    struct Scripted {
+        results: Mutex<VecDeque<TickerData>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl TickerDataSource for Scripted {
+        async fn get_ticker_data(&self) -> TickerData {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.results
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| TickerData {
+                    last_price: Some(dec!(100)),
+                    sources: vec!["s".to_owned()],
+                    ..Default::default()
+                })
+        }
+
+        fn name(&self) -> String {
+            "s".to_owned()
+        }
+    }
+
+    fn failure(kind: ErrorKind) -> TickerData {
+        TickerData::error(SourceError::new("s".to_owned(), kind, "failed".to_owned()))
+    }
+
+    fn policy() -> ResiliencePolicy {
+        ResiliencePolicy {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            failure_threshold: 100,
+            cooloff: Duration::from_millis(100),
+        }
+    }
+
+    fn resilient(
+        results: Vec<TickerData>,
+        policy: ResiliencePolicy,
+    ) -> (ResilientSource, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let source = Scripted {
+            results: Mutex::new(results.into()),
+            calls: calls.clone(),
+        };
+        (ResilientSource::new(Box::new(source), policy), calls)
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let (source, calls) = resilient(
+            vec![failure(ErrorKind::Timeout), failure(ErrorKind::Network)],
+            policy(),
+        );
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_retries() {
+        let (source, calls) = resilient(vec![failure(ErrorKind::Timeout); 5], policy());
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, None);
+        assert_eq!(ticker_data.errors[0].kind, ErrorKind::Timeout);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let (source, calls) = resilient(vec![failure(ErrorKind::Parse)], policy());
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.errors[0].kind, ErrorKind::Parse);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let (source, _) = resilient(
+            vec![],
+            ResiliencePolicy {
+                backoff: Duration::from_secs(1),
+                ..policy()
+            },
+        );
+        for _ in 0..100 {
+            let delay = source.retry_delay(0);
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+            let delay = source.retry_delay(3);
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(8));
+            for attempt in [5, 31, 32, u32::MAX] {
+                let delay = source.retry_delay(attempt);
+                assert!(
+                    delay >= MAX_BACKOFF / 2 && delay <= MAX_BACKOFF,
+                    "{:?}",
+                    delay
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_failing_source_until_cooloff() {
+        let (source, calls) = resilient(
+            vec![failure(ErrorKind::Parse), failure(ErrorKind::Exchange)],
+            ResiliencePolicy {
+                retries: 0,
+                failure_threshold: 2,
+                ..policy()
+            },
+        );
+        source.get_ticker_data().await;
+        source.get_ticker_data().await;
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.errors[0].kind, ErrorKind::Skipped);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let ticker_data = source.get_ticker_data().await;
+        assert_eq!(ticker_data.last_price, Some(dec!(100)));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // Closed again.
+        source.get_ticker_data().await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn unknown_symbols_do_not_open_the_breaker() {
+        let (source, calls) = resilient(
+            vec![failure(ErrorKind::UnknownSymbol); 3],
+            ResiliencePolicy {
+                failure_threshold: 2,
+                ..policy()
+            },
+        );
+        for _ in 0..3 {
+            let ticker_data = source.get_ticker_data().await;
+            assert_eq!(ticker_data.errors[0].kind, ErrorKind::UnknownSymbol);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
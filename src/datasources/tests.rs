@@ -0,0 +1,354 @@
+//! Runs the exchange sources against a local mock server serving recorded
+//! responses from `tests/fixtures`.
+
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use reqwest::Client;
+use rust_decimal_macros::dec;
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use super::{
+    BinanceTickerDataSource, CoinbaseTickerDataSource, ErrorKind, GoldpriceTickerDataSource,
+    KrakenTickerDataSource, TickerData, TickerDataSource,
+};
+
+/// Shorter than the mock's delay in `slow`, so those requests time out.
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+fn client() -> Arc<Client> {
+    Arc::new(Client::builder().timeout(TIMEOUT).build().unwrap())
+}
+
+fn json(status: u16, body: &str) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_raw(body.to_owned(), "application/json")
+}
+
+fn slow() -> ResponseTemplate {
+    json(200, "{}").set_delay(TIMEOUT * 5)
+}
+
+async fn serve(path_: &str, response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(path_))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    server
+}
+
+fn assert_failed(ticker_data: &TickerData, kind: ErrorKind, detail: &str) {
+    assert_eq!(ticker_data.last_price, None);
+    assert!(ticker_data.insufficient_data);
+    assert_eq!(ticker_data.errors.len(), 1, "{:?}", ticker_data.errors);
+    let error = &ticker_data.errors[0];
+    assert_eq!(error.kind, kind, "{:?}", error);
+    assert!(
+        error.detail.contains(detail),
+        "{:?} does not mention {:?}",
+        error,
+        detail
+    );
+    assert_eq!(ticker_data.is_transient(), kind.is_transient());
+}
+
+fn binance(server: &MockServer) -> BinanceTickerDataSource {
+    BinanceTickerDataSource::new(client(), server.uri(), "BTCUSDT".to_owned())
+}
+
+#[tokio::test]
+async fn binance_success() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/24hr"))
+        .and(query_param("symbol", "BTCUSDT"))
+        .respond_with(json(
+            200,
+            include_str!("../../tests/fixtures/binance_ticker_24hr.json"),
+        ))
+        .mount(&server)
+        .await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(67432.78)));
+    assert_eq!(ticker_data.prev_price, Some(dec!(67945.12)));
+    assert_eq!(ticker_data.volume, Some(dec!(18734.59312)));
+    assert_eq!(ticker_data.high, Some(dec!(68420)));
+    assert_eq!(ticker_data.low, Some(dec!(66871.23)));
+    assert_eq!(ticker_data.bid, Some(dec!(67432.77)));
+    assert_eq!(ticker_data.ask, Some(dec!(67432.78)));
+    assert_eq!(
+        ticker_data.timestamp,
+        Some(UNIX_EPOCH + Duration::from_millis(1718092799999))
+    );
+    assert_eq!(ticker_data.sources, ["binance:BTCUSDT"]);
+}
+
+#[tokio::test]
+async fn binance_error() {
+    let server = serve(
+        "/api/v3/ticker/24hr",
+        json(400, include_str!("../../tests/fixtures/binance_error.json")),
+    )
+    .await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::UnknownSymbol, "Invalid symbol.");
+}
+
+#[tokio::test]
+async fn binance_malformed() {
+    let server = serve(
+        "/api/v3/ticker/24hr",
+        json(200, r#"{"symbol":"BTCUSDT","lastPrice":67432.78}"#),
+    )
+    .await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert_failed(
+        &ticker_data,
+        ErrorKind::Parse,
+        "Failed to parse Binance response",
+    );
+}
+
+#[tokio::test]
+async fn binance_without_volume() {
+    let server = serve(
+        "/api/v3/ticker/24hr",
+        json(
+            200,
+            r#"{"symbol":"BTCUSDT","lastPrice":"67432.78","openPrice":"67945.12","volume":null}"#,
+        ),
+    )
+    .await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(67432.78)));
+    assert_eq!(ticker_data.volume, None);
+}
+
+#[tokio::test]
+async fn binance_server_error() {
+    let server = serve("/api/v3/ticker/24hr", json(503, "<html>")).await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::HttpStatus(503), "503");
+}
+
+#[tokio::test]
+async fn binance_timeout() {
+    let server = serve("/api/v3/ticker/24hr", slow()).await;
+    let ticker_data = binance(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::Timeout, "");
+}
+
+fn coinbase(server: &MockServer) -> CoinbaseTickerDataSource {
+    CoinbaseTickerDataSource::new(client(), server.uri(), "BTC-USD".to_owned())
+}
+
+async fn serve_coinbase(stats: ResponseTemplate, ticker: ResponseTemplate) -> MockServer {
+    let server = serve("/products/BTC-USD/stats", stats).await;
+    Mock::given(method("GET"))
+        .and(path("/products/BTC-USD/ticker"))
+        .respond_with(ticker)
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn coinbase_success() {
+    let server = serve_coinbase(
+        json(
+            200,
+            include_str!("../../tests/fixtures/coinbase_stats.json"),
+        ),
+        json(
+            200,
+            include_str!("../../tests/fixtures/coinbase_ticker.json"),
+        ),
+    )
+    .await;
+    let ticker_data = coinbase(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(67440.12)));
+    assert_eq!(ticker_data.prev_price, Some(dec!(67950.01)));
+    assert_eq!(ticker_data.volume, Some(dec!(9812.34567)));
+    assert_eq!(ticker_data.high, Some(dec!(68415.5)));
+    assert_eq!(ticker_data.low, Some(dec!(66880)));
+    assert_eq!(ticker_data.bid, Some(dec!(67440.12)));
+    assert_eq!(ticker_data.ask, Some(dec!(67440.13)));
+    assert_eq!(
+        ticker_data.timestamp,
+        Some(UNIX_EPOCH + Duration::from_micros(1718092799123456))
+    );
+    assert_eq!(ticker_data.sources, ["coinbase:BTC-USD"]);
+}
+
+#[tokio::test]
+async fn coinbase_error() {
+    let error = include_str!("../../tests/fixtures/coinbase_error.json");
+    let server = serve_coinbase(json(404, error), json(404, error)).await;
+    let ticker_data = coinbase(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::UnknownSymbol, "NotFound");
+}
+
+#[tokio::test]
+async fn coinbase_malformed() {
+    let server = serve_coinbase(
+        json(200, r#"{"open":"67950.01"}"#),
+        json(
+            200,
+            include_str!("../../tests/fixtures/coinbase_ticker.json"),
+        ),
+    )
+    .await;
+    let ticker_data = coinbase(&server).get_ticker_data().await;
+    assert_failed(
+        &ticker_data,
+        ErrorKind::Parse,
+        "Failed to parse Coinbase response",
+    );
+}
+
+#[tokio::test]
+async fn coinbase_timeout() {
+    let server = serve_coinbase(
+        slow(),
+        json(
+            200,
+            include_str!("../../tests/fixtures/coinbase_ticker.json"),
+        ),
+    )
+    .await;
+    let ticker_data = coinbase(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::Timeout, "");
+}
+
+#[tokio::test]
+async fn coinbase_ticker_error() {
+    let server = serve_coinbase(
+        json(
+            200,
+            include_str!("../../tests/fixtures/coinbase_stats.json"),
+        ),
+        json(503, "<html>"),
+    )
+    .await;
+    let ticker_data = coinbase(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(67440.12)));
+    assert_eq!(ticker_data.volume, Some(dec!(9812.34567)));
+    assert_eq!(ticker_data.bid, None);
+    assert_eq!(ticker_data.ask, None);
+    assert_eq!(ticker_data.timestamp, None);
+}
+
+fn kraken(server: &MockServer) -> KrakenTickerDataSource {
+    KrakenTickerDataSource::new(client(), server.uri(), "XXBTZUSD".to_owned())
+}
+
+#[tokio::test]
+async fn kraken_success() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/0/public/Ticker"))
+        .and(query_param("pair", "XXBTZUSD"))
+        .respond_with(json(
+            200,
+            include_str!("../../tests/fixtures/kraken_ticker.json"),
+        ))
+        .mount(&server)
+        .await;
+    let ticker_data = kraken(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(67441.2)));
+    // Today's, like the opening price, rather than the last 24 hours'.
+    assert_eq!(ticker_data.prev_price, Some(dec!(67931.4)));
+    assert_eq!(ticker_data.volume, Some(dec!(1523.48316744)));
+    assert_eq!(ticker_data.high, Some(dec!(68399.9)));
+    assert_eq!(ticker_data.low, Some(dec!(66890)));
+    assert_eq!(ticker_data.bid, Some(dec!(67441.1)));
+    assert_eq!(ticker_data.ask, Some(dec!(67441.2)));
+    assert_eq!(ticker_data.sources, ["kraken:XXBTZUSD"]);
+}
+
+#[tokio::test]
+async fn kraken_error() {
+    let server = serve(
+        "/0/public/Ticker",
+        json(200, include_str!("../../tests/fixtures/kraken_error.json")),
+    )
+    .await;
+    let ticker_data = kraken(&server).get_ticker_data().await;
+    assert_failed(
+        &ticker_data,
+        ErrorKind::UnknownSymbol,
+        "EQuery:Unknown asset pair",
+    );
+}
+
+#[tokio::test]
+async fn kraken_malformed() {
+    let server = serve("/0/public/Ticker", json(200, "not json")).await;
+    let ticker_data = kraken(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::Parse, "");
+}
+
+#[tokio::test]
+async fn kraken_timeout() {
+    let server = serve("/0/public/Ticker", slow()).await;
+    let ticker_data = kraken(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::Timeout, "");
+}
+
+fn goldprice(server: &MockServer) -> GoldpriceTickerDataSource {
+    GoldpriceTickerDataSource::new(client(), server.uri(), "XAU".to_owned(), "USD".to_owned())
+}
+
+#[tokio::test]
+async fn goldprice_success() {
+    let server = serve(
+        "/dbXRates/USD",
+        json(200, include_str!("../../tests/fixtures/goldprice.json")),
+    )
+    .await;
+    let ticker_data = goldprice(&server).get_ticker_data().await;
+    assert!(ticker_data.errors.is_empty(), "{:?}", ticker_data.errors);
+    assert_eq!(ticker_data.last_price, Some(dec!(2309.965)));
+    assert_eq!(ticker_data.prev_price, Some(dec!(2311.32)));
+    assert_eq!(
+        ticker_data.timestamp,
+        Some(UNIX_EPOCH + Duration::from_millis(1718092799123))
+    );
+    assert_eq!(ticker_data.sources, ["goldprice:XAU/USD"]);
+}
+
+#[tokio::test]
+async fn goldprice_error() {
+    let server = serve("/dbXRates/USD", json(500, "")).await;
+    let ticker_data = goldprice(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::HttpStatus(500), "500");
+}
+
+#[tokio::test]
+async fn goldprice_malformed() {
+    let server = serve("/dbXRates/USD", json(200, r#"{"items":[]}"#)).await;
+    let ticker_data = goldprice(&server).get_ticker_data().await;
+    assert_failed(
+        &ticker_data,
+        ErrorKind::Parse,
+        "Failed to parse Goldprice response",
+    );
+}
+
+#[tokio::test]
+async fn goldprice_timeout() {
+    let server = serve("/dbXRates/USD", slow()).await;
+    let ticker_data = goldprice(&server).get_ticker_data().await;
+    assert_failed(&ticker_data, ErrorKind::Timeout, "");
+}
@@ -1,90 +1,175 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use log::info;
 use rust_decimal::{prelude::FromPrimitive, Decimal};
 use std::{
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, UNIX_EPOCH},
 };
-use tokio::sync::Mutex;
+use yahoo_finance_api::{Quote, YahooError};
 
 use super::datasource::{TickerData, TickerDataSource};
+use super::error::{fail, ErrorKind};
 
 pub struct YahooFinanceTickerDataSource {
-    connector: Arc<yahoo_finance_api::YahooConnector>,
+    /// `None` while replaying a cassette, which Yahoo's traffic can't go
+    /// through; the source then fails rather than going live.
+    connector: Option<Arc<yahoo_finance_api::YahooConnector>>,
     ticker: String,
-    last_check_res: Mutex<Option<(Instant, TickerData)>>,
 }
 
 impl YahooFinanceTickerDataSource {
     pub fn new(
-        connector: Arc<yahoo_finance_api::YahooConnector>,
+        connector: Option<Arc<yahoo_finance_api::YahooConnector>>,
         ticker: String,
     ) -> YahooFinanceTickerDataSource {
-        YahooFinanceTickerDataSource {
-            connector,
-            ticker,
-            last_check_res: Mutex::new(None),
-        }
+        YahooFinanceTickerDataSource { connector, ticker }
     }
 
-    async fn run_query(&self) -> Result<(Option<Decimal>, Option<Decimal>)> {
-        let quotes = self
-            .connector
+    async fn run_query(&self) -> Result<TickerData> {
+        let connector = self.connector.as_ref().ok_or_else(|| {
+            fail(
+                ErrorKind::Other,
+                "Yahoo Finance is disabled while replaying a cassette".to_owned(),
+            )
+        })?;
+        let quotes = connector
             .get_quote_range(&self.ticker, "1d", "5d")
-            .await?
-            .quotes()?
-            .into_iter()
-            .rev()
-            .collect::<Vec<_>>();
+            .await
+            .and_then(|response| response.quotes())
+            .map_err(classify)?;
         info!("Yahoo: {} {:?}", &self.ticker, &quotes);
-        let prev_price = if quotes.len() >= 2 {
-            quotes[1].adjclose
-        } else {
-            0.
+        self.ticker_data(quotes)
+    }
+
+    /// The latest of daily `quotes`, against the close of the day before.
+    fn ticker_data(&self, mut quotes: Vec<Quote>) -> Result<TickerData> {
+        quotes.reverse();
+        let latest = quotes.first().ok_or_else(|| {
+            fail(
+                ErrorKind::Parse,
+                format!("Yahoo Finance returned no quotes for {}", self.ticker),
+            )
+        })?;
+        let last = Decimal::from_f64(latest.close).ok_or_else(|| {
+            fail(
+                ErrorKind::Parse,
+                "Failed to parse yfi price into decimal".to_owned(),
+            )
+        })?;
+        let prev = match quotes.get(1) {
+            Some(quote) if quote.adjclose != 0. => {
+                Some(Decimal::from_f64(quote.adjclose).ok_or_else(|| {
+                    fail(
+                        ErrorKind::Parse,
+                        "Failed to parse yfi price into decimal".to_owned(),
+                    )
+                })?)
+            }
+            _ => None,
         };
-        if quotes.is_empty() {
-            Ok((None, None))
-        } else if prev_price == 0. {
-            let last = Decimal::from_f64(quotes[0].close)
-                .ok_or(anyhow!("Failed to parse yfi price into decimal"))?;
-            Ok((Some(last), None))
-        } else {
-            let last = Decimal::from_f64(quotes[0].close)
-                .ok_or(anyhow!("Failed to parse yfi price into decimal"))?;
-            let prev = Decimal::from_f64(prev_price)
-                .ok_or(anyhow!("Failed to parse yfi price into decimal"))?;
-            Ok((Some(last), Some(prev)))
-        }
+        Ok(TickerData {
+            last_price: Some(last),
+            prev_price: prev,
+            insufficient_data: false,
+            volume: Decimal::from_u64(latest.volume).filter(|volume| !volume.is_zero()),
+            high: Decimal::from_f64(latest.high),
+            low: Decimal::from_f64(latest.low),
+            bid: None,
+            ask: None,
+            timestamp: Some(UNIX_EPOCH + Duration::from_secs(latest.timestamp)),
+            latency: None,
+            fetched_at: None,
+            sources: vec![self.name()],
+            rejected: vec![],
+            errors: vec![],
+            degraded: false,
+        })
     }
 }
 
+/// Classifies a Yahoo Finance error the way `SourceError::classify` does
+/// HTTP client errors, so that retries and health see its kind.
+fn classify(error: YahooError) -> anyhow::Error {
+    let kind = match &error {
+        // Raised with the response's status, e.g. `404 Not Found`.
+        YahooError::FetchFailed(status) => status
+            .split(|c: char| !c.is_ascii_digit())
+            .find_map(|code| code.parse().ok().filter(|code| (100..600).contains(code)))
+            .map_or(ErrorKind::Other, ErrorKind::HttpStatus),
+        YahooError::ConnectionFailed(e) if e.is_timeout() => ErrorKind::Timeout,
+        YahooError::ConnectionFailed(_) => ErrorKind::Network,
+        YahooError::DeserializeFailed(_)
+        | YahooError::InvalidJson
+        | YahooError::EmptyDataSet
+        | YahooError::DataInconsistency => ErrorKind::Parse,
+        _ => ErrorKind::Other,
+    };
+    fail(kind, error.to_string())
+}
+
 #[async_trait]
 impl TickerDataSource for YahooFinanceTickerDataSource {
     async fn get_ticker_data(&self) -> TickerData {
-        let mut last_check_res = self.last_check_res.lock().await;
-        if let Some((ref time, ref ticker_data)) = *last_check_res {
-            if time.elapsed() < Duration::from_secs(5) {
-                return ticker_data.clone();
-            }
-        }
-        match self.run_query().await {
-            Ok((last_price, prev_price)) => {
-                let ticker_data = TickerData {
-                    last_price,
-                    prev_price,
-                    insufficient_data: last_price.is_none(),
-                    errors: vec![],
-                };
-                *last_check_res = Some((Instant::now(), ticker_data.clone()));
-                ticker_data
-            }
-            Err(e) => TickerData {
-                last_price: None,
-                prev_price: None,
-                insufficient_data: true,
-                errors: vec![e.to_string()],
-            },
+        self.run_query()
+            .await
+            .unwrap_or_else(|e| TickerData::failed(self.name(), e))
+    }
+
+    fn name(&self) -> String {
+        format!("yahoo:{}", self.ticker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use yahoo_finance_api::{Quote, YahooError};
+
+    use super::{classify, YahooFinanceTickerDataSource};
+    use crate::datasources::{ErrorKind, SourceError};
+
+    fn kind(error: YahooError) -> ErrorKind {
+        SourceError::classify("yahoo:SPY".to_owned(), &classify(error)).kind
+    }
+
+    #[test]
+    fn classifies_errors() {
+        assert_eq!(
+            kind(YahooError::FetchFailed(
+                "503 Service Unavailable".to_owned()
+            )),
+            ErrorKind::HttpStatus(503)
+        );
+        assert_eq!(kind(YahooError::InvalidJson), ErrorKind::Parse);
+        assert_eq!(kind(YahooError::EmptyDataSet), ErrorKind::Parse);
+        assert_eq!(kind(YahooError::BuilderFailed), ErrorKind::Other);
+    }
+
+    fn quote(timestamp: u64, close: f64) -> Quote {
+        Quote {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            volume: 100,
+            close,
+            adjclose: close,
         }
     }
+
+    #[test]
+    fn latest_against_previous_close() {
+        let source = YahooFinanceTickerDataSource::new(None, "SPY".to_owned());
+        let ticker_data = source
+            .ticker_data(vec![quote(1, 500.), quote(2, 505.5)])
+            .unwrap();
+        assert_eq!(ticker_data.last_price, Some(dec!(505.5)));
+        assert_eq!(ticker_data.prev_price, Some(dec!(500)));
+        let error = source.ticker_data(vec![]).err().unwrap();
+        assert_eq!(
+            SourceError::classify("yahoo:SPY".to_owned(), &error).kind,
+            ErrorKind::Parse
+        );
+    }
 }
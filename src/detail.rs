@@ -0,0 +1,99 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use pretty_duration::pretty_duration;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::render::{Align, Document, Table};
+use crate::DataSources;
+
+const USAGE: &str = "Usage: /detail <ticker>";
+
+/// Handles `/detail` and returns the reply.
+pub async fn handle_command(
+    args: &str,
+    currency: &str,
+    data_sources: &DataSources,
+) -> Result<Document> {
+    let ticker = match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [ticker] => ticker.to_ascii_uppercase(),
+        _ => return Ok(Document::text(USAGE)),
+    };
+    let ticker_data = match data_sources.get_quoted(&ticker, currency).await {
+        Some(ticker_data) => ticker_data,
+        None => return Ok(Document::text(format!("Unknown ticker: {}", ticker))),
+    };
+    let mut rows = Table::new(&[Align::Left, Align::Left]);
+    let mut row = |name: &str, value: String| {
+        rows.row([name.to_owned(), value]);
+    };
+    let price = |price: Option<Decimal>| {
+        price
+            .map(|price| format!("{:.2}", price))
+            .unwrap_or("N/A".to_owned())
+    };
+    let change = match (ticker_data.last_price, ticker_data.prev_price) {
+        (Some(last), Some(prev)) if !prev.is_zero() => {
+            format!(" {:+.2}%", ((last / prev).to_f64().unwrap() - 1.) * 100.)
+        }
+        _ => String::new(),
+    };
+    row("Last", price(ticker_data.last_price) + &change);
+    row("Prev", price(ticker_data.prev_price));
+    row("High", price(ticker_data.high));
+    row("Low", price(ticker_data.low));
+    row("Bid", price(ticker_data.bid));
+    let spread = match (ticker_data.bid, ticker_data.ask) {
+        (Some(bid), Some(ask)) if !bid.is_zero() => {
+            format!(
+                " (spread {:.3}%)",
+                ((ask / bid).to_f64().unwrap() - 1.) * 100.
+            )
+        }
+        _ => String::new(),
+    };
+    row("Ask", price(ticker_data.ask) + &spread);
+    row(
+        "Volume",
+        ticker_data
+            .volume
+            .map(|volume| volume.round_dp(2).to_string())
+            .unwrap_or("N/A".to_owned()),
+    );
+    row(
+        "Updated",
+        ticker_data
+            .timestamp
+            .map(|time| {
+                let age = SystemTime::now().duration_since(time).unwrap_or_default();
+                format!(
+                    "{} ({} ago)",
+                    DateTime::<Utc>::from(time).format("%Y-%m-%d %H:%M:%S UTC"),
+                    pretty_duration(&Duration::from_secs(age.as_secs()), None)
+                )
+            })
+            .unwrap_or("N/A".to_owned()),
+    );
+    row("Sources", ticker_data.sources.join(", "));
+    for rejected in &ticker_data.rejected {
+        row("Dropped", rejected.clone());
+    }
+    for error in &ticker_data.errors {
+        row("Error", error.summary());
+    }
+    let mut document = Document::text(format!(
+        "{} in {}{}",
+        ticker,
+        currency,
+        if ticker_data.insufficient_data {
+            " (insufficient data)"
+        } else if ticker_data.degraded {
+            " (degraded)"
+        } else {
+            ""
+        }
+    ));
+    document.table(&rows);
+    Ok(document)
+}
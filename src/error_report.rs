@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use log::error;
+use pretty_duration::pretty_duration;
+use teloxide::{types::ChatId, Bot};
+
+use crate::config::ErrorReportConfig;
+use crate::datasources::{ErrorKind, SourceError};
+use crate::render::{self, Document};
+
+/// Longest upstream detail quoted in a report; some quote whole bodies.
+const MAX_DETAIL_LEN: usize = 300;
+
+/// Collects source errors and sends them to the admin chat in batches, one
+/// line per source and error kind, so public chats only need to say how
+/// many sources were unavailable.
+pub struct ErrorReporter {
+    bot: Bot,
+    chat: ChatId,
+    interval: Duration,
+    pending: Mutex<HashMap<(String, ErrorKind), Pending>>,
+}
+
+struct Pending {
+    count: u64,
+    /// The most recent error of this source and kind.
+    latest: SourceError,
+}
+
+impl ErrorReporter {
+    pub fn new(bot: Bot, config: &ErrorReportConfig) -> ErrorReporter {
+        ErrorReporter {
+            bot,
+            chat: ChatId(config.chat),
+            interval: Duration::from_secs(config.interval_secs),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, error: &SourceError) {
+        let mut pending = self.pending.lock().unwrap();
+        pending
+            .entry((error.source.clone(), error.kind))
+            .and_modify(|pending| {
+                pending.count += 1;
+                pending.latest = error.clone();
+            })
+            .or_insert_with(|| Pending {
+                count: 1,
+                latest: error.clone(),
+            });
+    }
+
+    /// The errors recorded since the last report, if there were any.
+    fn take_report(&self) -> Option<Document> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return None;
+        }
+        let mut entries: Vec<_> = pending.into_values().collect();
+        entries.sort_by_key(|entry| (entry.latest.source.clone(), entry.latest.kind.name()));
+        let mut report = Document::text(format!(
+            "Source errors in the last {}:",
+            pretty_duration(&self.interval, None)
+        ));
+        for entry in &entries {
+            let mut detail = entry.latest.detail.clone();
+            if let Some((cut, _)) = detail.char_indices().nth(MAX_DETAIL_LEN) {
+                detail.truncate(cut);
+                detail.push('…');
+            }
+            report
+                .bold(format!("{} ×{}", entry.latest.summary(), entry.count))
+                .pre([detail]);
+        }
+        Some(report)
+    }
+
+    pub async fn monitor(&self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Some(report) = self.take_report() {
+                if let Err(e) = render::send(&self.bot, self.chat, &report, None, None).await {
+                    error!("error report: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::Bot;
+
+    use super::ErrorReporter;
+    use crate::config::ErrorReportConfig;
+    use crate::datasources::{ErrorKind, SourceError};
+
+    fn error(source: &str, kind: ErrorKind, detail: &str) -> SourceError {
+        SourceError::new(source.to_owned(), kind, detail.to_owned())
+    }
+
+    #[test]
+    fn deduplicates_by_source_and_kind() {
+        let reporter = ErrorReporter::new(
+            Bot::new("0:test"),
+            &ErrorReportConfig {
+                chat: 1,
+                interval_secs: 600,
+            },
+        );
+        assert!(reporter.take_report().is_none());
+        reporter.record(&error("kraken:XXBTZUSD", ErrorKind::Timeout, "first"));
+        reporter.record(&error("binance:BTCUSDT", ErrorKind::Timeout, "a"));
+        reporter.record(&error("kraken:XXBTZUSD", ErrorKind::Timeout, "second"));
+        reporter.record(&error("kraken:XXBTZUSD", ErrorKind::Parse, "bad"));
+        let report = reporter.take_report().unwrap().render();
+        assert_eq!(report.len(), 1);
+        let lines: Vec<_> = report[0].lines().filter(|l| l.starts_with('*')).collect();
+        assert_eq!(
+            lines,
+            [
+                "*binance:BTCUSDT: timed out ×1*",
+                "*kraken:XXBTZUSD: unexpected response ×1*",
+                "*kraken:XXBTZUSD: timed out ×2*",
+            ]
+        );
+        assert!(report[0].contains("second") && !report[0].contains("first"));
+        assert!(reporter.take_report().is_none());
+    }
+}
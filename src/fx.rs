@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rust_decimal::Decimal;
+use yahoo_finance_api::YahooConnector;
+
+use crate::datasources::{
+    CachePolicy, CachedSource, ErrorKind, SourceError, TickerData, TickerDataSource,
+    YahooFinanceTickerDataSource,
+};
+
+/// Fiat currencies that can be converted to and from. Prices everywhere
+/// else are in USD, so rates are all against it.
+pub const CURRENCIES: &[&str] = &[
+    "USD", "EUR", "JPY", "CNY", "GBP", "CHF", "CAD", "AUD", "HKD", "SGD", "KRW",
+];
+
+pub fn is_currency(code: &str) -> bool {
+    CURRENCIES.contains(&code)
+}
+
+/// Foreign exchange rates against USD, read from Yahoo Finance's `XXXUSD=X`
+/// quotes.
+pub struct FxRates {
+    yfi: Option<Arc<YahooConnector>>,
+    sources: Mutex<HashMap<String, Arc<CachedSource>>>,
+}
+
+impl FxRates {
+    pub fn new(yfi: Option<Arc<YahooConnector>>) -> FxRates {
+        FxRates {
+            yfi,
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The USD value of one unit of `currency`, or `None` if it isn't a
+    /// known currency or no rate is available.
+    pub async fn usd_rate(&self, currency: &str) -> Option<Decimal> {
+        if currency == "USD" {
+            return Some(Decimal::ONE);
+        }
+        if !is_currency(currency) {
+            return None;
+        }
+        let source = self
+            .sources
+            .lock()
+            .unwrap()
+            .entry(currency.to_owned())
+            .or_insert_with(|| {
+                let source = YahooFinanceTickerDataSource::new(
+                    self.yfi.clone(),
+                    format!("{}USD=X", currency),
+                );
+                let label = source.name();
+                Arc::new(CachedSource::new(
+                    Box::new(source),
+                    rate_cache_policy(),
+                    label,
+                ))
+            })
+            .clone();
+        source.get_ticker_data().await.last_price
+    }
+}
+
+/// Converts USD prices into `currency`, given the USD value of one unit of
+/// it. Without a usable rate the prices are dropped, rather than shown in
+/// the wrong currency, and the error says why.
+pub fn reprice(
+    ticker_data: &mut TickerData,
+    currency: &str,
+    usd_rate: Option<Decimal>,
+) -> Result<(), SourceError> {
+    let rate = usd_rate.filter(|rate| !rate.is_zero());
+    for price in [
+        &mut ticker_data.last_price,
+        &mut ticker_data.prev_price,
+        &mut ticker_data.high,
+        &mut ticker_data.low,
+        &mut ticker_data.bid,
+        &mut ticker_data.ask,
+    ] {
+        *price = rate.and_then(|rate| price.and_then(|price| price.checked_div(rate)));
+    }
+    if rate.is_none() {
+        ticker_data.insufficient_data = true;
+        return Err(SourceError::new(
+            format!("fx:{}", currency),
+            ErrorKind::NoExchangeRate,
+            format!("No exchange rate for {}", currency),
+        ));
+    }
+    Ok(())
+}
+
+/// Exchange rates move slowly compared to the prices they convert.
+fn rate_cache_policy() -> CachePolicy {
+    CachePolicy {
+        ttl: Duration::from_secs(60),
+        stale: Duration::from_secs(600),
+        error_ttl: Duration::from_secs(10),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::{reprice, FxRates};
+    use crate::datasources::{ErrorKind, TickerData};
+
+    fn usd_prices() -> TickerData {
+        TickerData {
+            last_price: Some(dec!(100)),
+            prev_price: Some(dec!(50)),
+            high: Some(dec!(125)),
+            low: Some(dec!(25)),
+            bid: Some(dec!(99.5)),
+            ask: Some(dec!(100.5)),
+            volume: Some(dec!(7)),
+            sources: vec!["binance:BTCUSDT".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reprices_at_rate() {
+        let mut ticker_data = usd_prices();
+        reprice(&mut ticker_data, "EUR", Some(dec!(1.25))).unwrap();
+        assert_eq!(ticker_data.last_price, Some(dec!(80)));
+        assert_eq!(ticker_data.prev_price, Some(dec!(40)));
+        assert_eq!(ticker_data.high, Some(dec!(100)));
+        assert_eq!(ticker_data.low, Some(dec!(20)));
+        assert_eq!(ticker_data.bid, Some(dec!(79.6)));
+        assert_eq!(ticker_data.ask, Some(dec!(80.4)));
+        // In the base asset, not a price.
+        assert_eq!(ticker_data.volume, Some(dec!(7)));
+        assert!(!ticker_data.insufficient_data);
+    }
+
+    #[test]
+    fn drops_prices_without_rate() {
+        for rate in [None, Some(dec!(0))] {
+            let mut ticker_data = usd_prices();
+            let error = reprice(&mut ticker_data, "JPY", rate).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::NoExchangeRate);
+            assert_eq!(error.source, "fx:JPY");
+            assert_eq!(error.detail, "No exchange rate for JPY");
+            assert_eq!(ticker_data.last_price, None);
+            assert_eq!(ticker_data.prev_price, None);
+            assert_eq!(ticker_data.high, None);
+            assert_eq!(ticker_data.bid, None);
+            assert!(ticker_data.insufficient_data);
+            assert_eq!(ticker_data.sources, ["binance:BTCUSDT"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn usd_rates_offline() {
+        let fx = FxRates::new(None);
+        assert_eq!(fx.usd_rate("USD").await, Some(dec!(1)));
+        assert_eq!(fx.usd_rate("XYZ").await, None);
+        // Yahoo Finance is off, so there is no rate.
+        assert_eq!(fx.usd_rate("EUR").await, None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, SystemTime};
+
+use pretty_duration::pretty_duration;
+
+use crate::datasources::HealthRegistry;
+use crate::render::{Align, Document, Table};
+
+/// Handles `/health` and returns the reply.
+pub fn handle_command(health: &HealthRegistry) -> Document {
+    let report = health.report();
+    if report.is_empty() {
+        return Document::text("No sources queried yet");
+    }
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let ago = |time: Option<u64>| {
+        time.map(|time| {
+            pretty_duration(&Duration::from_secs(now.saturating_sub(time)), None) + " ago"
+        })
+        .unwrap_or("never".to_owned())
+    };
+    let millis = |ms: Option<u64>| ms.map(|ms| format!("{}ms", ms)).unwrap_or("-".to_owned());
+
+    let mut table = Table::new(&[Align::Left, Align::Right, Align::Right, Align::Right]);
+    table.row(["Source", "OK", "p50", "p95"]);
+    let mut notes = vec![];
+    for source in &report {
+        table.row([
+            source.name.clone(),
+            format!("{:.0}%", source.success_rate * 100.),
+            millis(source.p50_latency_ms),
+            millis(source.p95_latency_ms),
+        ]);
+        notes.push(format!(
+            "{}: {} queries, {} failed, last success {}",
+            source.name,
+            source.queries,
+            source.failures,
+            ago(source.last_success)
+        ));
+        // Only the kind: the detail can quote URLs or response bodies, and
+        // this goes to whichever chat asked.
+        if let Some(error) = &source.last_error {
+            notes.push(format!("  {} {}", ago(source.last_error_at), error.kind));
+        }
+    }
+    let mut document = Document::text("Source health (success rate over the last 100 queries):");
+    document.pre(
+        table
+            .lines()
+            .into_iter()
+            .chain([String::new()])
+            .chain(notes),
+    );
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::handle_command;
+    use crate::datasources::{
+        ErrorKind, HealthRegistry, MonitoredSource, SourceError, TickerData, TickerDataSource,
+    };
+
+    struct Failing;
+
+    #[async_trait]
+    impl TickerDataSource for Failing {
+        async fn get_ticker_data(&self) -> TickerData {
+            TickerData::error(SourceError::new(
+                self.name(),
+                ErrorKind::HttpStatus(503),
+                "503 Service Unavailable".to_owned(),
+            ))
+        }
+
+        fn name(&self) -> String {
+            "kraken:XXBTZUSD".to_owned()
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_sources() {
+        let health = Arc::new(HealthRegistry::new());
+        assert_eq!(handle_command(&health).render(), ["No sources queried yet"]);
+        let source = MonitoredSource::new(Box::new(Failing), health.clone(), "kraken:*".to_owned());
+        source.get_ticker_data().await;
+        let message = handle_command(&health).render().concat();
+        // Named by the source itself, not by its metrics label.
+        assert!(
+            message.contains("kraken:XXBTZUSD: 1 queries, 1 failed, last success never"),
+            "{}",
+            message
+        );
+        assert!(message.contains(" ago HTTP 503"), "{}", message);
+        assert!(!message.contains("Service Unavailable"), "{}", message);
+        let row: Vec<_> = message
+            .lines()
+            .find(|line| line.starts_with("kraken:XXBTZUSD "))
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        assert_eq!(row[1], "0%");
+    }
+}
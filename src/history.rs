@@ -0,0 +1,275 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use log::{error, info};
+use rusqlite::{params, Connection};
+
+use crate::candles::{self, Candle, Interval};
+use crate::datasources::TickerData;
+use crate::resolver::SourceSpec;
+use crate::storage::{from_unix, to_unix};
+use crate::DataSources;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS observations (
+    time INTEGER NOT NULL,
+    ticker TEXT NOT NULL,
+    price TEXT NOT NULL,
+    sources TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS observations_ticker_time ON observations (ticker, time);
+CREATE TABLE IF NOT EXISTS daily_candles (
+    ticker TEXT NOT NULL,
+    source TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    open TEXT NOT NULL,
+    high TEXT NOT NULL,
+    low TEXT NOT NULL,
+    close TEXT NOT NULL,
+    PRIMARY KEY (ticker, source, time)
+);
+";
+
+/// Long-term price history in its own SQLite file: every aggregated price
+/// the bot observes, plus daily candles imported from upstream.
+pub struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    pub fn open(path: &str) -> Result<History> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(History {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn record(&self, ticker: &str, ticker_data: &TickerData) {
+        let price = match ticker_data.last_price {
+            Some(price) => price,
+            None => return,
+        };
+        let result = self.conn.lock().unwrap().execute(
+            "INSERT INTO observations (time, ticker, price, sources) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                to_unix(SystemTime::now()),
+                ticker,
+                price.to_string(),
+                ticker_data.sources.join(",")
+            ],
+        );
+        if let Err(e) = result {
+            error!("Record observation of {}: {}", ticker, e);
+        }
+    }
+
+    /// Stores daily candles, replacing any already stored for the same day.
+    pub fn add_daily_candles(&self, ticker: &str, source: &str, candles: &[Candle]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO daily_candles (ticker, source, time, open, high, low, close)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for candle in candles {
+                stmt.execute(params![
+                    ticker,
+                    source,
+                    to_unix(candle.time),
+                    candle.open.to_string(),
+                    candle.high.to_string(),
+                    candle.low.to_string(),
+                    candle.close.to_string()
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn last_daily_candle(&self, ticker: &str, source: &str) -> Result<Option<SystemTime>> {
+        let conn = self.conn.lock().unwrap();
+        let time: Option<i64> = conn.query_row(
+            "SELECT MAX(time) FROM daily_candles WHERE ticker = ?1 AND source = ?2",
+            params![ticker, source],
+            |row| row.get(0),
+        )?;
+        Ok(time.map(from_unix))
+    }
+}
+
+/// Imports daily candles for the configured tickers from every source that
+/// has them, at startup and then once a day. The first run goes back
+/// `days`; later runs only fetch what's missing.
+pub struct Backfill {
+    history: Arc<History>,
+    data_sources: Arc<DataSources>,
+    days: usize,
+}
+
+impl Backfill {
+    pub fn new(history: Arc<History>, data_sources: Arc<DataSources>, days: usize) -> Backfill {
+        Backfill {
+            history,
+            data_sources,
+            days,
+        }
+    }
+
+    pub async fn monitor(&self) {
+        loop {
+            self.run().await;
+            tokio::time::sleep(Interval::Day.duration()).await;
+        }
+    }
+
+    async fn run(&self) {
+        for ticker in &self.data_sources.default_tickers {
+            let ticker = ticker.to_ascii_uppercase();
+            let specs = match self.data_sources.resolver.specs(&ticker).await {
+                Some(specs) => specs,
+                None => continue,
+            };
+            for spec in specs.iter().filter(|spec| candles::supported(spec)) {
+                if let Err(e) = self.backfill(&ticker, spec).await {
+                    error!("Backfill {} from {}: {}", ticker, spec, e);
+                }
+            }
+        }
+    }
+
+    async fn backfill(&self, ticker: &str, spec: &SourceSpec) -> Result<()> {
+        let source = spec.to_string();
+        let count = days_to_fetch(
+            self.history.last_daily_candle(ticker, &source)?,
+            SystemTime::now(),
+            self.days,
+        );
+        let candles = self
+            .data_sources
+            .resolver
+            .candles(spec, Interval::Day, count)
+            .await?;
+        info!(
+            "Backfill {} from {}: {} candles",
+            ticker,
+            source,
+            candles.len()
+        );
+        self.history.add_daily_candles(ticker, &source, &candles)
+    }
+}
+
+/// How many daily candles to fetch, given when the last stored one opened.
+fn days_to_fetch(last: Option<SystemTime>, now: SystemTime, days: usize) -> usize {
+    match last {
+        // Refetch the last stored day too, as it may have been partial.
+        Some(last) => {
+            let missing = now.duration_since(last).unwrap_or_default().as_secs()
+                / Interval::Day.duration().as_secs();
+            (missing as usize + 1).min(days)
+        }
+        None => days,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{days_to_fetch, History};
+    use crate::candles::Candle;
+    use crate::datasources::TickerData;
+    use crate::storage::from_unix;
+
+    const DAY: u64 = 24 * 3600;
+
+    fn candle(day: u64, close: Decimal) -> Candle {
+        Candle {
+            time: from_unix((day * DAY) as i64),
+            open: close,
+            high: close,
+            low: close,
+            close,
+        }
+    }
+
+    #[test]
+    fn fetches_only_missing_days() {
+        let now = from_unix((100 * DAY + 3600) as i64);
+        let ago = |secs: u64| Some(now - Duration::from_secs(secs));
+        assert_eq!(days_to_fetch(None, now, 365), 365);
+        // Today's candle is refetched, as it was still open.
+        assert_eq!(days_to_fetch(ago(3600), now, 365), 1);
+        assert_eq!(days_to_fetch(ago(3 * DAY + 3600), now, 365), 4);
+        assert_eq!(days_to_fetch(ago(1000 * DAY), now, 365), 365);
+        // A clock that went back.
+        let ahead = now + Duration::from_secs(DAY);
+        assert_eq!(days_to_fetch(Some(ahead), now, 365), 1);
+    }
+
+    #[test]
+    fn daily_candles_replace_stored_days() {
+        let history = History::open(":memory:").unwrap();
+        let source = "binance:BTCUSDT";
+        assert_eq!(history.last_daily_candle("BTC", source).unwrap(), None);
+        history
+            .add_daily_candles("BTC", source, &[candle(1, dec!(1)), candle(2, dec!(2))])
+            .unwrap();
+        history
+            .add_daily_candles("BTC", source, &[candle(2, dec!(20)), candle(3, dec!(3))])
+            .unwrap();
+        history
+            .add_daily_candles("BTC", "yahoo:BTC-USD", &[candle(5, dec!(5))])
+            .unwrap();
+        assert_eq!(
+            history.last_daily_candle("BTC", source).unwrap(),
+            Some(from_unix((3 * DAY) as i64))
+        );
+        let conn = history.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT close FROM daily_candles WHERE source = ?1 ORDER BY time")
+            .unwrap();
+        let closes: Vec<String> = stmt
+            .query_map([source], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(closes, ["1", "20", "3"]);
+    }
+
+    #[test]
+    fn records_prices_with_their_sources() {
+        let history = History::open(":memory:").unwrap();
+        history.record(
+            "BTC",
+            &TickerData {
+                last_price: Some(dec!(67432.78)),
+                sources: vec!["binance:BTCUSDT".to_owned(), "kraken:XXBTZUSD".to_owned()],
+                ..Default::default()
+            },
+        );
+        // Nothing to record without a price.
+        history.record("ETH", &TickerData::default());
+        let conn = history.conn.lock().unwrap();
+        let (time, ticker, price, sources): (i64, String, String, String) = conn
+            .query_row(
+                "SELECT time, ticker, price, sources FROM observations",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert!(from_unix(time) <= SystemTime::now());
+        assert_eq!(ticker, "BTC");
+        assert_eq!(price, "67432.78");
+        assert_eq!(sources, "binance:BTCUSDT,kraken:XXBTZUSD");
+    }
+}
@@ -0,0 +1,328 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::coinbase_monitor::{CoinbaseMonitor, Product};
+use crate::config::HttpConfig;
+use crate::datasources::{SourceError, SourceHealth, TickerData};
+use crate::fx;
+use crate::metrics::METRICS;
+use crate::DataSources;
+
+/// A local HTTP server exposing the bot's state: source health as JSON at
+/// `/health`, Prometheus metrics at `/metrics` and, if enabled, prices under
+/// `/v1`.
+pub struct HttpServer {
+    listen: SocketAddr,
+    api: bool,
+    state: AppState,
+}
+
+#[derive(Clone)]
+struct AppState {
+    data_sources: Arc<DataSources>,
+    cb_monitor: Arc<CoinbaseMonitor>,
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+/// One ticker as served by the API. Decimals are strings, times unix
+/// seconds.
+#[derive(Serialize)]
+struct Price {
+    ticker: String,
+    currency: String,
+    price: Option<Decimal>,
+    prev_price: Option<Decimal>,
+    change_percent: Option<Decimal>,
+    high: Option<Decimal>,
+    low: Option<Decimal>,
+    bid: Option<Decimal>,
+    ask: Option<Decimal>,
+    volume: Option<Decimal>,
+    timestamp: Option<u64>,
+    sources: Vec<String>,
+    rejected: Vec<String>,
+    errors: Vec<SourceError>,
+    insufficient_data: bool,
+    degraded: bool,
+}
+
+impl Price {
+    fn new(ticker: String, currency: &str, ticker_data: TickerData) -> Price {
+        Price {
+            ticker,
+            currency: currency.to_owned(),
+            price: ticker_data.last_price,
+            prev_price: ticker_data.prev_price,
+            change_percent: ticker_data.last_price.zip(ticker_data.prev_price).and_then(
+                |(last, prev)| {
+                    let change = last.checked_div(prev)?.checked_sub(Decimal::ONE)?;
+                    Some(change.checked_mul(Decimal::ONE_HUNDRED)?.round_dp(4))
+                },
+            ),
+            high: ticker_data.high,
+            low: ticker_data.low,
+            bid: ticker_data.bid,
+            ask: ticker_data.ask,
+            volume: ticker_data.volume,
+            timestamp: ticker_data
+                .timestamp
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|time| time.as_secs()),
+            sources: ticker_data.sources,
+            rejected: ticker_data.rejected,
+            errors: ticker_data.errors,
+            insufficient_data: ticker_data.insufficient_data,
+            degraded: ticker_data.degraded,
+        }
+    }
+}
+
+impl HttpServer {
+    pub fn new(
+        config: &HttpConfig,
+        data_sources: Arc<DataSources>,
+        cb_monitor: Arc<CoinbaseMonitor>,
+    ) -> HttpServer {
+        HttpServer {
+            listen: config.listen,
+            api: config.api,
+            state: AppState {
+                data_sources,
+                cb_monitor,
+            },
+        }
+    }
+
+    fn router(&self) -> Router {
+        let mut app = Router::new()
+            .route("/health", get(health))
+            .route("/metrics", get(metrics));
+        if self.api {
+            app = app
+                .route("/v1/prices", get(prices))
+                .route("/v1/prices/:ticker", get(price))
+                .route("/v1/coinbase/products/:id", get(coinbase_product));
+        }
+        app.with_state(self.state.clone())
+    }
+
+    pub async fn serve(&self) {
+        let app = self.router();
+        let listener = match tokio::net::TcpListener::bind(self.listen).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("http: bind {}: {}", self.listen, e);
+                return;
+            }
+        };
+        info!("http: listening on {}", self.listen);
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("http: {}", e);
+        }
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
+
+async fn health(State(state): State<AppState>) -> Json<Vec<SourceHealth>> {
+    Json(state.data_sources.resolver.health().report())
+}
+
+/// The `currency` query parameter, USD if not given.
+fn currency(params: &HashMap<String, String>) -> Result<String, (StatusCode, String)> {
+    let currency = params
+        .get("currency")
+        .map(|currency| currency.to_ascii_uppercase())
+        .unwrap_or("USD".to_owned());
+    if fx::is_currency(&currency) {
+        Ok(currency)
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported currency: {}", currency),
+        ))
+    }
+}
+
+/// The configured tickers, as `/query` shows them.
+async fn prices(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<Vec<Price>> {
+    let currency = currency(&params)?;
+    let query = state.data_sources.query_all(&currency).await;
+    Ok(Json(
+        query
+            .quotes
+            .into_iter()
+            .filter_map(|(ticker, ticker_data)| Some(Price::new(ticker, &currency, ticker_data?)))
+            .collect(),
+    ))
+}
+
+async fn price(
+    State(state): State<AppState>,
+    Path(ticker): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<Price> {
+    let currency = currency(&params)?;
+    let query = state.data_sources.query(&[ticker], &currency).await;
+    match query.quotes.into_iter().next() {
+        Some((ticker, Some(ticker_data))) => Ok(Json(Price::new(ticker, &currency, ticker_data))),
+        Some((ticker, None)) => Err((StatusCode::NOT_FOUND, format!("Unknown ticker: {}", ticker))),
+        None => Err((StatusCode::NOT_FOUND, "Unknown ticker".to_owned())),
+    }
+}
+
+async fn coinbase_product(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Product> {
+    let id = id.to_ascii_uppercase();
+    match state.cb_monitor.product(&id).await {
+        Some(product) => Ok(Json(product)),
+        None => Err((StatusCode::NOT_FOUND, format!("Unknown product: {}", id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use reqwest::Client;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use serde_json::Value as JsonValue;
+    use tower::ServiceExt;
+    use wiremock::MockServer;
+
+    use super::{HttpServer, Price};
+    use crate::coinbase_monitor::CoinbaseMonitor;
+    use crate::config::HttpConfig;
+    use crate::datasources::TickerData;
+    use crate::metrics::METRICS;
+    use crate::test_util::{data_sources, serve_binance};
+
+    /// The routes with BTC configured from Binance, served by `server`.
+    async fn router(server: &MockServer, api: bool) -> Router {
+        serve_binance(server).await;
+        let config = HttpConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            api,
+        };
+        let data_sources = Arc::new(data_sources(&server.uri(), &["BTC"]));
+        let cb_monitor = Arc::new(CoinbaseMonitor::new(Arc::new(Client::new()), server.uri()));
+        HttpServer::new(&config, data_sources, cb_monitor).router()
+    }
+
+    async fn get(router: &Router, uri: &str) -> (StatusCode, Option<String>, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn decimal(value: &JsonValue) -> Decimal {
+        value.as_str().unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn prices() {
+        let server = MockServer::start().await;
+        let router = router(&server, true).await;
+        let (status, _, body) = get(&router, "/v1/prices").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let prices: JsonValue = serde_json::from_str(&body).unwrap();
+        let prices = prices.as_array().unwrap();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0]["ticker"], "BTC");
+        assert_eq!(prices[0]["currency"], "USD");
+        assert_eq!(decimal(&prices[0]["price"]), dec!(67432.78));
+        assert_eq!(decimal(&prices[0]["volume"]), dec!(18734.59312));
+        assert_eq!(prices[0]["timestamp"], 1718092799);
+        assert_eq!(prices[0]["sources"][0], "binance:BTCUSDT");
+
+        let (status, _, body) = get(&router, "/v1/prices?currency=xyz").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "Unsupported currency: XYZ");
+    }
+
+    #[tokio::test]
+    async fn price_by_ticker() {
+        let server = MockServer::start().await;
+        let router = router(&server, true).await;
+        let (status, _, body) = get(&router, "/v1/prices/btc").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let price: JsonValue = serde_json::from_str(&body).unwrap();
+        assert_eq!(price["ticker"], "BTC");
+        assert_eq!(decimal(&price["prev_price"]), dec!(67945.12));
+
+        let (status, _, body) = get(&router, "/v1/prices/NOPE").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "Unknown ticker: NOPE");
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let server = MockServer::start().await;
+        let router = router(&server, false).await;
+        // Not served without the API.
+        let (status, ..) = get(&router, "/v1/prices").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        METRICS.commands.with_label_values(&["http-test"]).inc();
+        let (status, content_type, body) = get(&router, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some("text/plain; version=0.0.4"));
+        assert!(
+            body.lines()
+                .any(|line| line == "ireina_commands_total{command=\"http-test\"} 1"),
+            "{}",
+            body
+        );
+    }
+
+    #[test]
+    fn change_percent() {
+        let price = |last, prev| {
+            let ticker_data = TickerData {
+                last_price: Some(last),
+                prev_price: Some(prev),
+                ..Default::default()
+            };
+            Price::new("BTC".to_owned(), "USD", ticker_data).change_percent
+        };
+        assert_eq!(price(dec!(105), dec!(100)), Some(dec!(5)));
+        assert_eq!(price(dec!(105), dec!(0)), None);
+        assert_eq!(price(Decimal::MAX, dec!(0.5)), None);
+    }
+}
@@ -1,32 +1,67 @@
+mod alerts;
+mod breakdown;
+mod candles;
+mod cassette;
+mod chart;
 mod coinbase_monitor;
+mod config;
+mod convert;
+mod currency;
 mod datasources;
+mod detail;
+mod error_report;
+mod fx;
+mod health;
+mod history;
+mod http;
+mod metrics;
+mod move_alerts;
+mod render;
+mod resolver;
+mod series;
+mod storage;
+mod subscriptions;
+mod symbols;
+#[cfg(test)]
+mod test_util;
+mod watchlist;
 
+use alerts::AlertMonitor;
 use anyhow::Result;
+use cassette::CassetteServer;
+use chart::ChartReply;
 use coinbase_monitor::CoinbaseMonitor;
-use datasources::Aggregator;
-use datasources::BinanceTickerDataSource;
-use datasources::CoinbaseTickerDataSource;
-use datasources::GoldpriceTickerDataSource;
-use datasources::KrakenTickerDataSource;
-use datasources::TickerData;
-use datasources::YahooFinanceTickerDataSource;
+use config::{CassetteConfig, CassetteMode, Config};
+use currency::chat_currency;
+use datasources::{ErrorKind, SourceError, TickerData};
 use env_logger::Env;
+use error_report::ErrorReporter;
 use futures::future::join_all;
+use fx::FxRates;
+use history::{Backfill, History};
+use http::HttpServer;
 use log::error;
 use log::warn;
+use metrics::METRICS;
+use render::{Align, Document, Table, PARSE_MODE};
 use reqwest::Client;
+use resolver::Resolver;
 use rust_decimal::prelude::*;
+use series::PriceSeries;
 use std::convert::TryInto as _;
 use std::env;
 use std::sync::Arc;
 use std::time::Duration;
+use storage::Storage;
+use subscriptions::DigestScheduler;
+use symbols::{AssetPair, SymbolMap};
 use teloxide::dispatching::Dispatcher;
 use teloxide::dispatching::HandlerExt;
 use teloxide::dispatching::UpdateFilterExt;
 use teloxide::dptree;
 use teloxide::macros::BotCommands;
 use teloxide::payloads::AnswerInlineQuerySetters;
-use teloxide::payloads::SendMessageSetters;
+use teloxide::payloads::SendPhotoSetters;
 use teloxide::requests::Request;
 use teloxide::requests::Requester;
 use teloxide::types::InlineKeyboardButton;
@@ -35,6 +70,7 @@ use teloxide::types::InlineQuery;
 use teloxide::types::InlineQueryResultArticle;
 use teloxide::types::InlineQueryResultsButton;
 use teloxide::types::InlineQueryResultsButtonKind;
+use teloxide::types::InputFile;
 use teloxide::types::InputMessageContent;
 use teloxide::types::InputMessageContentText;
 use teloxide::types::Message;
@@ -44,37 +80,139 @@ use teloxide::types::WebAppInfo;
 use teloxide::Bot;
 use yahoo_finance_api::YahooConnector;
 
-use datasources::TickerDataSource;
+/// Hard limit on how many tickers a single `/query` may ask for. A chat's
+/// watchlist, used when none are given, has its own limit.
+const MAX_QUERY_TICKERS: usize = 10;
 
 struct DataSources {
-    btc: Box<dyn TickerDataSource + Sync>,
-    eth: Box<dyn TickerDataSource + Sync>,
-    sol: Box<dyn TickerDataSource + Sync>,
-    gspc: Box<dyn TickerDataSource + Sync>,
-    ixic: Box<dyn TickerDataSource + Sync>,
-    xau: Box<dyn TickerDataSource + Sync>,
+    default_tickers: Vec<String>,
+    resolver: Resolver,
+    series: PriceSeries,
+    history: Option<Arc<History>>,
+    fx: FxRates,
+    error_reporter: Option<Arc<ErrorReporter>>,
 }
 
 struct QueryState {
-    tickers: Vec<(String, String, String, bool)>,
-    errors: Vec<String>,
+    currency: String,
+    /// What each ticker was fetched as, or `None` if it is unknown.
+    quotes: Vec<(String, Option<TickerData>)>,
+    /// Ticker, price, change and a marker: `*` for insufficient data, `~`
+    /// when some sources failed.
+    tickers: Vec<(String, String, String, &'static str)>,
+    /// Outlier sources left out of a ticker's price.
+    rejected: Vec<String>,
+    /// Unknown tickers and missing exchange rates.
+    notices: Vec<String>,
+    /// Sources that failed, out of all the tickers' sources.
+    unavailable: usize,
+    source_count: usize,
 }
 
 impl DataSources {
-    async fn query_all(&self) -> QueryState {
-        let results = join_all([
-            self.btc.get_ticker_data(),
-            self.eth.get_ticker_data(),
-            self.sol.get_ticker_data(),
-            self.gspc.get_ticker_data(),
-            self.ixic.get_ticker_data(),
-            self.xau.get_ticker_data(),
-        ])
-        .await;
-        let tickers = results
+    fn from_config(
+        config: &Config,
+        client: Arc<Client>,
+        yfi: Option<Arc<YahooConnector>>,
+        symbols: Arc<SymbolMap>,
+        storage: &Arc<Storage>,
+        history: Option<Arc<History>>,
+        error_reporter: Option<Arc<ErrorReporter>>,
+    ) -> Result<DataSources> {
+        let series_storage = if config.persist_price_series {
+            Some(storage.clone())
+        } else {
+            None
+        };
+        Ok(DataSources {
+            default_tickers: config.tickers.iter().map(|t| t.name.clone()).collect(),
+            fx: FxRates::new(yfi.clone()),
+            resolver: Resolver::new(config, client, yfi, symbols)?,
+            series: PriceSeries::new(series_storage)?,
+            history,
+            error_reporter,
+        })
+    }
+
+    /// Fetches one ticker, recording its price in the rolling series and
+    /// the history, and its errors in the log and the error reports.
+    async fn get_ticker_data(&self, ticker: &str) -> Option<TickerData> {
+        let source = self.resolver.resolve(ticker).await?;
+        let ticker_data = source.get_ticker_data().await;
+        for error in &ticker_data.errors {
+            self.report(error);
+        }
+        let ticker = ticker.to_ascii_uppercase();
+        if let Some(price) = ticker_data.last_price {
+            self.series.record(&ticker, price);
+            // Only configured tickers, so the number of series stays bounded.
+            if self
+                .default_tickers
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(&ticker))
+            {
+                if let Some(price) = price.to_f64() {
+                    METRICS.prices.with_label_values(&[&ticker]).set(price);
+                }
+            }
+        }
+        if let Some(history) = &self.history {
+            history.record(&ticker, &ticker_data);
+        }
+        Some(ticker_data)
+    }
+
+    /// Fetches one ticker priced in `currency`, from a pair quoted in it
+    /// where an exchange lists one and otherwise converted at the FX rate.
+    async fn get_quoted(&self, ticker: &str, currency: &str) -> Option<TickerData> {
+        if currency == "USD" {
+            return self.get_ticker_data(ticker).await;
+        }
+        if let Some(pair) = self.resolver.pair(ticker).await {
+            if pair.quote == "USD" || pair.quote == "USDT" {
+                let native = AssetPair::new(&pair.base, currency).to_string();
+                if let Some(ticker_data) = self.get_ticker_data(&native).await {
+                    if ticker_data.last_price.is_some() {
+                        return Some(ticker_data);
+                    }
+                }
+            }
+        }
+        let mut ticker_data = self.get_ticker_data(ticker).await?;
+        let rate = self.fx.usd_rate(currency).await;
+        if let Err(error) = fx::reprice(&mut ticker_data, currency, rate) {
+            self.report(&error);
+            ticker_data.errors.push(error);
+        }
+        Some(ticker_data)
+    }
+
+    fn report(&self, error: &SourceError) {
+        warn!("{}", error);
+        if let Some(error_reporter) = &self.error_reporter {
+            error_reporter.record(error);
+        }
+    }
+
+    async fn query_all(&self, currency: &str) -> QueryState {
+        self.query(&self.default_tickers, currency).await
+    }
+
+    async fn query(&self, tickers: &[String], currency: &str) -> QueryState {
+        let results = join_all(tickers.iter().map(|t| self.get_quoted(t, currency))).await;
+        let mut notices = vec![];
+        let tickers: Vec<_> = results
             .iter()
-            .zip(["BTC", "ETH", "SOL", "GSPC", "IXIC", "XAU"])
+            .zip(tickers)
             .map(|(ticker_data, ticker)| {
+                let ticker = ticker.to_ascii_uppercase();
+                let ticker_data = match ticker_data {
+                    Some(ticker_data) => ticker_data,
+                    None => {
+                        notices.push(format!("Unknown ticker: {}", ticker));
+                        return (ticker, "N/A".to_owned(), "N/A".to_owned(), "*");
+                    }
+                };
                 let change = {
                     if let TickerData {
                         last_price: Some(last),
@@ -91,68 +229,181 @@ impl DataSources {
                     .last_price
                     .map(|price| format!("{:>.2}", price))
                     .unwrap_or("N/A".to_owned());
-                (
-                    ticker.to_owned(),
-                    price,
-                    change,
-                    ticker_data.insufficient_data,
-                )
+                let marker = if ticker_data.insufficient_data {
+                    "*"
+                } else if ticker_data.degraded {
+                    "~"
+                } else {
+                    ""
+                };
+                (ticker, price, change, marker)
+            })
+            .collect();
+        let rejected = results
+            .iter()
+            .zip(&tickers)
+            .flat_map(|(ticker_data, (ticker, ..))| {
+                ticker_data
+                    .iter()
+                    .flat_map(move |t| t.rejected.iter().map(move |r| format!("{}: {}", ticker, r)))
             })
             .collect();
-        let errors = results.into_iter().flat_map(|t| t.errors).collect();
+        // Upstream errors can be long and quote URLs or response bodies, so
+        // chats only get a count; the log and the error reports, fed as the
+        // errors are fetched, get the rest.
+        let mut unavailable = 0;
+        let mut source_count = 0;
+        for ticker_data in results.iter().flatten() {
+            let mut failed = 0;
+            for error in &ticker_data.errors {
+                match error.kind {
+                    ErrorKind::NoExchangeRate => {
+                        if !notices.contains(&error.detail) {
+                            notices.push(error.detail.clone());
+                        }
+                    }
+                    // Not a source of its own.
+                    ErrorKind::Quorum => {}
+                    _ => failed += 1,
+                }
+            }
+            unavailable += failed;
+            source_count += ticker_data.sources.len() + ticker_data.rejected.len() + failed;
+        }
+        let quotes = tickers
+            .iter()
+            .map(|(ticker, ..)| ticker.clone())
+            .zip(results)
+            .collect();
 
-        QueryState { tickers, errors }
+        QueryState {
+            currency: currency.to_owned(),
+            quotes,
+            tickers,
+            rejected,
+            notices,
+            unavailable,
+            source_count,
+        }
     }
 }
 
-async fn gen_message(state: &QueryState) -> Result<String> {
-    let errmsg = if state.errors.is_empty() {
-        String::new()
-    } else {
-        warn!("{}", state.errors.join("\n"));
-        format!(
-            "\nError happened while fetching prices:\n{}",
-            state.errors.join("\n")
-        )
-    };
-    let width_ticker = state.tickers.iter().map(|s| s.0.len()).max().unwrap_or(4);
-    let width_price = state.tickers.iter().map(|s| s.1.len()).max().unwrap_or(8);
-    let width_change = state.tickers.iter().map(|s| s.2.len()).max().unwrap_or(8);
-    let output = state
-        .tickers
-        .iter()
-        .map(|(ticker, price, change, insufficient)| {
-            format!(
-                "{:<width_ticker$} {:>width_price$} {:>width_change$}",
-                ticker, price, change
-            ) + if *insufficient { " *" } else { "" }
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-    Ok(format!("```\n{}```{}", output, errmsg))
+async fn gen_message(state: &QueryState) -> Result<Document> {
+    let mut document = Document::new();
+    if state.currency != "USD" {
+        document.bold(format!("Prices in {}", state.currency));
+    }
+    let mut table = Table::new(&[Align::Left, Align::Right, Align::Right, Align::Left]);
+    for (ticker, price, change, marker) in &state.tickers {
+        table.row([ticker.as_str(), price, change, *marker]);
+    }
+    document.table(&table);
+    if !state.rejected.is_empty() {
+        document.line("Discarded outliers:");
+        for rejected in &state.rejected {
+            document.line(rejected);
+        }
+    }
+    for notice in &state.notices {
+        document.line(notice);
+    }
+    if state.unavailable > 0 {
+        document.line(format!(
+            "{} of {} sources unavailable",
+            state.unavailable, state.source_count
+        ));
+    }
+    Ok(document)
 }
 
-async fn get_update(data_sources: &DataSources) -> Result<String> {
-    let query_result = data_sources.query_all().await;
-    let msgstr = gen_message(&query_result).await?;
-    Ok(msgstr)
+async fn get_update(
+    data_sources: &DataSources,
+    tickers: &[String],
+    currency: &str,
+) -> Result<Document> {
+    let query_result = if tickers.is_empty() {
+        data_sources.query_all(currency).await
+    } else {
+        data_sources.query(tickers, currency).await
+    };
+    gen_message(&query_result).await
 }
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command {
-    #[command(description = "query coin price")]
-    Query,
+    #[command(description = "query prices, optionally for the given tickers")]
+    Query(String),
+    #[command(description = "volume, range, order book and sources of one ticker")]
+    Detail(String),
+    #[command(description = "what each source of a ticker reports")]
+    Sources(String),
+    #[command(description = "how each upstream source has been doing (admins only)")]
+    Health,
     #[command(description = "query coinbase product")]
     CbStatus(String),
+    #[command(description = "manage this chat's watchlist: add|remove|list")]
+    Watch(String),
+    #[command(description = "price alerts: <ticker> >|< <price> [repeat], list, del <id>")]
+    Alert(String),
+    #[command(
+        description = "move alerts: <ticker> <percent>% <window> [cooldown], list, del <id>"
+    )]
+    MoveAlert(String),
+    #[command(description = "price chart: <ticker> [1d|7d|1m|1y] [line|candle]")]
+    Chart(String),
+    #[command(description = "convert between assets and currencies: <amount> <from> <to>")]
+    Convert(String),
+    #[command(description = "show or set this chat's display currency")]
+    Currency(String),
+    #[command(description = "scheduled price digests: hourly, daily <HH:MM> [tz], list, ...")]
+    Subscribe(String),
+    #[command(description = "cancel a scheduled price digest")]
+    Unsubscribe(String),
+}
+
+impl Command {
+    /// The command as typed, without the slash.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Query(_) => "query",
+            Command::Detail(_) => "detail",
+            Command::Sources(_) => "sources",
+            Command::Health => "health",
+            Command::CbStatus(_) => "cbstatus",
+            Command::Watch(_) => "watch",
+            Command::Alert(_) => "alert",
+            Command::MoveAlert(_) => "movealert",
+            Command::Chart(_) => "chart",
+            Command::Convert(_) => "convert",
+            Command::Currency(_) => "currency",
+            Command::Subscribe(_) => "subscribe",
+            Command::Unsubscribe(_) => "unsubscribe",
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     let token = env::var("IREINA_TOKEN")?;
+    let config_path = env::var("IREINA_CONFIG").unwrap_or("ireina.toml".to_owned());
+    let mut config = Config::load(&config_path)?;
+    let cassette = match &config.cassette {
+        Some(cassette) => {
+            let (server, endpoints) = CassetteServer::bind(cassette, &config.endpoints).await?;
+            config.endpoints = endpoints;
+            Some(server)
+        }
+        None => None,
+    };
+    let config = Arc::new(config);
     let bot = Bot::new(token);
 
+    if let Err(e) = chart::load_font(&config.chart_font) {
+        warn!("Charts will not render: {}", e);
+    }
+
     let http_client = Arc::new(
         Client::builder()
             .user_agent("ireina/0.1.0")
@@ -162,73 +413,50 @@ async fn main() -> Result<()> {
             .unwrap(),
     );
 
-    let yfi = Arc::new(YahooConnector::new()?);
-
-    let data_sources = DataSources {
-        btc: Box::new(Aggregator::new(vec![
-            Box::new(BinanceTickerDataSource::new(
-                http_client.clone(),
-                "BTCUSDT".to_string(),
-            )),
-            Box::new(CoinbaseTickerDataSource::new(
-                http_client.clone(),
-                "BTC-USD".to_string(),
-            )),
-            Box::new(KrakenTickerDataSource::new(
-                http_client.clone(),
-                "XXBTZUSD".to_string(),
-            )),
-        ])),
-        eth: Box::new(Aggregator::new(vec![
-            Box::new(BinanceTickerDataSource::new(
-                http_client.clone(),
-                "ETHUSDT".to_string(),
-            )),
-            Box::new(CoinbaseTickerDataSource::new(
-                http_client.clone(),
-                "ETH-USD".to_string(),
-            )),
-            Box::new(KrakenTickerDataSource::new(
-                http_client.clone(),
-                "XETHZUSD".to_string(),
-            )),
-        ])),
-        sol: Box::new(Aggregator::new(vec![
-            Box::new(BinanceTickerDataSource::new(
-                http_client.clone(),
-                "SOLUSDT".to_string(),
-            )),
-            Box::new(CoinbaseTickerDataSource::new(
-                http_client.clone(),
-                "SOL-USD".to_string(),
-            )),
-            Box::new(KrakenTickerDataSource::new(
-                http_client.clone(),
-                "SOLUSD".to_string(),
-            )),
-        ])),
-        gspc: Box::new(YahooFinanceTickerDataSource::new(
-            yfi.clone(),
-            "^GSPC".to_string(),
-        )),
-        ixic: Box::new(YahooFinanceTickerDataSource::new(
-            yfi.clone(),
-            "^IXIC".to_string(),
-        )),
-        xau: Box::new(Aggregator::new(vec![
-            Box::new(YahooFinanceTickerDataSource::new(
-                yfi.clone(),
-                "GC=F".to_string(),
-            )),
-            Box::new(GoldpriceTickerDataSource::new(
-                http_client.clone(),
-                "XAU".to_string(),
-                "USD".to_string(),
-            )),
-        ])),
+    // Yahoo's client can't be pointed at the cassette, so rather than go
+    // live while replaying, its sources fail.
+    let yfi = match &config.cassette {
+        Some(CassetteConfig {
+            mode: CassetteMode::Replay,
+            ..
+        }) => {
+            warn!("Yahoo Finance is disabled while replaying a cassette");
+            None
+        }
+        _ => Some(Arc::new(YahooConnector::new()?)),
+    };
+
+    let symbols = Arc::new(SymbolMap::new(
+        http_client.clone(),
+        config.endpoints.clone(),
+    ));
+    symbols.refresh().await;
+
+    let storage = Arc::new(Storage::open(&config.database)?);
+    let history = match &config.history {
+        Some(history) => Some(Arc::new(History::open(&history.database)?)),
+        None => None,
     };
 
-    let cb_monitor = Arc::new(CoinbaseMonitor::new(http_client.clone()));
+    let error_reporter = config
+        .error_reports
+        .as_ref()
+        .map(|error_reports| Arc::new(ErrorReporter::new(bot.clone(), error_reports)));
+
+    let data_sources = Arc::new(DataSources::from_config(
+        &config,
+        http_client.clone(),
+        yfi,
+        symbols.clone(),
+        &storage,
+        history.clone(),
+        error_reporter.clone(),
+    )?);
+
+    let cb_monitor = Arc::new(CoinbaseMonitor::new(
+        http_client.clone(),
+        config.endpoints.coinbase.clone(),
+    ));
 
     let handler = dptree::entry()
         .branch(
@@ -239,11 +467,30 @@ async fn main() -> Result<()> {
         .branch(Update::filter_inline_query().endpoint(inline_query_handler))
         .endpoint(ignore_handler); // ignore the rest
 
+    let alert_monitor = AlertMonitor::new(bot.clone(), storage.clone(), data_sources.clone());
+    let digest_scheduler = DigestScheduler::new(bot.clone(), storage.clone(), data_sources.clone());
+
+    let backfill = history
+        .zip(config.history.as_ref())
+        .map(|(history, history_config)| {
+            Backfill::new(history, data_sources.clone(), history_config.backfill_days)
+        });
+
+    let http_server = config
+        .http
+        .as_ref()
+        .map(|http| HttpServer::new(http, data_sources.clone(), cb_monitor.clone()));
+
     let cb_monitor_clone = cb_monitor.clone();
     let bot_task = tokio::spawn(async move {
         Dispatcher::builder(bot, handler)
             .enable_ctrlc_handler()
-            .dependencies(dptree::deps![Arc::new(data_sources), cb_monitor_clone])
+            .dependencies(dptree::deps![
+                data_sources,
+                cb_monitor_clone,
+                storage,
+                config
+            ])
             .build()
             .dispatch()
             .await;
@@ -253,6 +500,42 @@ async fn main() -> Result<()> {
         cb_monitor.monitor().await;
     });
 
+    let _symbols_task = tokio::spawn(async move {
+        symbols.monitor().await;
+    });
+
+    let _alert_task = tokio::spawn(async move {
+        alert_monitor.monitor().await;
+    });
+
+    let _digest_task = tokio::spawn(async move {
+        digest_scheduler.monitor().await;
+    });
+
+    let _backfill_task = backfill.map(|backfill| {
+        tokio::spawn(async move {
+            backfill.monitor().await;
+        })
+    });
+
+    let _error_report_task = error_reporter.map(|error_reporter| {
+        tokio::spawn(async move {
+            error_reporter.monitor().await;
+        })
+    });
+
+    let _cassette_task = cassette.map(|cassette| {
+        tokio::spawn(async move {
+            cassette.serve().await;
+        })
+    });
+
+    let _http_task = http_server.map(|http_server| {
+        tokio::spawn(async move {
+            http_server.serve().await;
+        })
+    });
+
     bot_task.await?;
     Ok(())
 }
@@ -263,38 +546,252 @@ async fn command_handler(
     cmd: Command,
     data_sources: Arc<DataSources>,
     cb_monitor: Arc<CoinbaseMonitor>,
+    storage: Arc<Storage>,
+    config: Arc<Config>,
 ) -> Result<()> {
+    METRICS.commands.with_label_values(&[cmd.name()]).inc();
     let resp = match cmd {
-        Command::Query => {
-            let update = match get_update(&data_sources).await {
+        Command::Query(args) => {
+            let mut tickers: Vec<_> = args.split_whitespace().map(|s| s.to_owned()).collect();
+            if tickers.len() > MAX_QUERY_TICKERS {
+                render::send(
+                    &bot,
+                    msg.chat.id,
+                    &Document::text(format!(
+                        "Too many tickers, at most {} allowed",
+                        MAX_QUERY_TICKERS
+                    )),
+                    Some(msg.id),
+                    None,
+                )
+                .await?;
+                return Ok(());
+            }
+            if tickers.is_empty() {
+                tickers = chat_tickers(&storage, msg.chat.id.0);
+            }
+            let currency = chat_currency(&storage, msg.chat.id.0);
+            let mut update = match get_update(&data_sources, &tickers, &currency).await {
                 Ok(update) => update,
                 Err(e) => {
                     error!("get_update: {}", e);
                     return Ok(());
                 }
             };
-            let cbcmp = cb_monitor
-                .query_cmp()
-                .await
-                .map(|s| format!("\n**Coinbase Listing Change:**\n```\n{}\n```", s))
-                .unwrap_or_default();
-            bot.send_message(msg.chat.id, update + &cbcmp)
-                .reply_parameters(ReplyParameters::new(msg.id))
-                .parse_mode(teloxide::types::ParseMode::Markdown)
-                .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+            if let Some(cbcmp) = cb_monitor.query_cmp().await {
+                update.bold("Coinbase Listing Change:").pre(cbcmp.lines());
+            }
+            render::send(
+                &bot,
+                msg.chat.id,
+                &update,
+                Some(msg.id),
+                Some(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
                     "Gift Dev!",
                     "https://t.me/ireina_bot/gifting".try_into().unwrap(),
-                )]]))
+                )]])),
+            )
+            .await
+        }
+        Command::Detail(args) => {
+            let currency = chat_currency(&storage, msg.chat.id.0);
+            let reply = detail::handle_command(&args, &currency, &data_sources)
                 .await
+                .unwrap_or_else(|e| {
+                    error!("detail: {}", e);
+                    Document::text("Failed to fetch details")
+                });
+            render::send(&bot, msg.chat.id, &reply, Some(msg.id), None).await
+        }
+        Command::Sources(args) => {
+            let reply = breakdown::handle_command(&args, &data_sources.resolver)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("sources: {}", e);
+                    Document::text("Failed to fetch sources")
+                });
+            render::send(&bot, msg.chat.id, &reply, Some(msg.id), None).await
+        }
+        Command::Health => {
+            let admin = msg
+                .from
+                .as_ref()
+                .is_some_and(|user| config.admins.contains(&user.id.0));
+            let reply = if admin {
+                health::handle_command(data_sources.resolver.health())
+            } else {
+                Document::text("This command is for admins only")
+            };
+            render::send(&bot, msg.chat.id, &reply, Some(msg.id), None).await
         }
         Command::CbStatus(ticker) => {
             let status = match cb_monitor.query(&ticker).await {
-                Some(value) => serde_json::to_string(&value).unwrap(),
-                None => "Not found".to_owned(),
+                Some(product) => product.document(),
+                None => Document::text("Not found"),
+            };
+            render::send(&bot, msg.chat.id, &status, Some(msg.id), None).await
+        }
+        Command::Watch(args) => {
+            let reply = match watchlist::handle_command(
+                &args,
+                msg.chat.id.0,
+                &storage,
+                &data_sources.resolver,
+            )
+            .await
+            {
+                Ok(reply) => reply,
+                Err(e) => {
+                    error!("watch: {}", e);
+                    "Failed to update watchlist".to_owned()
+                }
+            };
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::Alert(args) => {
+            let reply = match alerts::handle_command(
+                &args,
+                msg.chat.id.0,
+                &storage,
+                &data_sources.resolver,
+            )
+            .await
+            {
+                Ok(reply) => reply,
+                Err(e) => {
+                    error!("alert: {}", e);
+                    "Failed to update alerts".to_owned()
+                }
+            };
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::MoveAlert(args) => {
+            let reply = match move_alerts::handle_command(
+                &args,
+                msg.chat.id.0,
+                &storage,
+                &data_sources.resolver,
+            )
+            .await
+            {
+                Ok(reply) => reply,
+                Err(e) => {
+                    error!("movealert: {}", e);
+                    "Failed to update move alerts".to_owned()
+                }
             };
-            bot.send_message(msg.chat.id, status)
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::Chart(args) => match chart::handle_command(&args, &data_sources.resolver).await {
+            Ok(ChartReply::Chart { png, caption }) => bot
+                .send_photo(msg.chat.id, InputFile::memory(png).file_name("chart.png"))
+                .caption(caption)
                 .reply_parameters(ReplyParameters::new(msg.id))
                 .await
+                .map(|_| ()),
+            Ok(ChartReply::Text(reply)) => {
+                render::send(
+                    &bot,
+                    msg.chat.id,
+                    &Document::text(reply),
+                    Some(msg.id),
+                    None,
+                )
+                .await
+            }
+            Err(e) => {
+                error!("chart: {}", e);
+                render::send(
+                    &bot,
+                    msg.chat.id,
+                    &Document::text("Failed to render chart"),
+                    Some(msg.id),
+                    None,
+                )
+                .await
+            }
+        },
+        Command::Convert(args) => {
+            let reply = convert::handle_command(&args, data_sources.as_ref())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("convert: {}", e);
+                    "Failed to convert".to_owned()
+                });
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::Currency(args) => {
+            let reply =
+                currency::handle_command(&args, msg.chat.id.0, &storage).unwrap_or_else(|e| {
+                    error!("currency: {}", e);
+                    "Failed to update currency".to_owned()
+                });
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::Subscribe(args) => {
+            let reply = subscriptions::handle_subscribe(&args, msg.chat.id.0, &storage)
+                .unwrap_or_else(|e| {
+                    error!("subscribe: {}", e);
+                    "Failed to update subscriptions".to_owned()
+                });
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
+        }
+        Command::Unsubscribe(args) => {
+            let reply = subscriptions::handle_unsubscribe(&args, msg.chat.id.0, &storage)
+                .unwrap_or_else(|e| {
+                    error!("unsubscribe: {}", e);
+                    "Failed to update subscriptions".to_owned()
+                });
+            render::send(
+                &bot,
+                msg.chat.id,
+                &Document::text(reply),
+                Some(msg.id),
+                None,
+            )
+            .await
         }
     };
     if let Err(ref e) = resp {
@@ -307,14 +804,21 @@ async fn inline_query_handler(
     bot: Bot,
     q: InlineQuery,
     data_sources: Arc<DataSources>,
+    storage: Arc<Storage>,
 ) -> Result<()> {
-    let update = match get_update(&data_sources).await {
+    // Inline queries have no chat; use the sender's private chat watchlist.
+    let tickers = chat_tickers(&storage, q.from.id.0 as i64);
+    let currency = chat_currency(&storage, q.from.id.0 as i64);
+    let update = match get_update(&data_sources, &tickers, &currency).await {
         Ok(update) => update,
         Err(e) => {
             error!("get_update: {}", e);
             return Ok(());
         }
     };
+    // An inline result is a single message, so it gets only the first part;
+    // watchlists are short enough for that to be all of it.
+    let text = update.render().into_iter().next().unwrap_or_default();
     let resp = bot
         .answer_inline_query(
             &q.id,
@@ -322,8 +826,7 @@ async fn inline_query_handler(
                 "price",
                 "Coin Prices",
                 InputMessageContent::Text(
-                    InputMessageContentText::new(update)
-                        .parse_mode(teloxide::types::ParseMode::Markdown),
+                    InputMessageContentText::new(text).parse_mode(PARSE_MODE),
                 ),
             )
             .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
@@ -346,6 +849,62 @@ async fn inline_query_handler(
     Ok(())
 }
 
+/// The chat's watchlist, or nothing (meaning the default tickers) if it has
+/// none.
+fn chat_tickers(storage: &Storage, chat_id: i64) -> Vec<String> {
+    storage.watchlist(chat_id).unwrap_or_else(|e| {
+        error!("watchlist: {}", e);
+        vec![]
+    })
+}
+
 async fn ignore_handler() -> Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use wiremock::MockServer;
+
+    use crate::test_util::{data_sources, serve_binance};
+
+    #[tokio::test]
+    async fn query_in_usd() {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        let data_sources = data_sources(&server.uri(), &["BTC"]);
+        let state = data_sources.query_all("USD").await;
+        assert_eq!(
+            state.tickers,
+            [(
+                "BTC".to_owned(),
+                "67432.78".to_owned(),
+                "-0.75%".to_owned(),
+                ""
+            )]
+        );
+        assert!(state.notices.is_empty());
+        assert_eq!((state.unavailable, state.source_count), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn query_without_exchange_rate() {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        let data_sources = data_sources(&server.uri(), &["BTC"]);
+        let state = data_sources
+            .query(&["btc".to_owned(), "nope/nope".to_owned()], "EUR")
+            .await;
+        assert_eq!(state.currency, "EUR");
+        assert_eq!(
+            state.tickers[0],
+            ("BTC".to_owned(), "N/A".to_owned(), "N/A".to_owned(), "*")
+        );
+        assert_eq!(
+            state.notices,
+            ["Unknown ticker: NOPE/NOPE", "No exchange rate for EUR"]
+        );
+        // The sources answered; only the conversion failed.
+        assert_eq!((state.unavailable, state.source_count), (0, 1));
+    }
+}
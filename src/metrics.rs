@@ -0,0 +1,182 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+/// Everything exported to Prometheus. Counters are only ever incremented, so
+/// ratios such as the cache hit ratio are left to queries.
+pub struct Metrics {
+    registry: Registry,
+    /// Upstream queries by source, e.g. `binance:BTCUSDT` for configured
+    /// tickers and `binance:*` for all others, so users can't add series.
+    pub source_requests: IntCounterVec,
+    /// Errors by source and `ErrorKind`.
+    pub source_errors: IntCounterVec,
+    pub source_latency: HistogramVec,
+    /// Cache lookups by source and result: `hit`, `stale` or `miss`.
+    pub cache_lookups: IntCounterVec,
+    /// Aggregated USD prices of the configured tickers.
+    pub prices: GaugeVec,
+    pub commands: IntCounterVec,
+    /// Coinbase product list polls by result: `ok` or `error`.
+    pub coinbase_polls: IntCounterVec,
+    pub coinbase_products: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            source_requests: IntCounterVec::new(
+                Opts::new("ireina_source_requests_total", "Upstream queries"),
+                &["source"],
+            )
+            .unwrap(),
+            source_errors: IntCounterVec::new(
+                Opts::new("ireina_source_errors_total", "Upstream query errors"),
+                &["source", "kind"],
+            )
+            .unwrap(),
+            source_latency: HistogramVec::new(
+                HistogramOpts::new("ireina_source_latency_seconds", "Upstream query latency")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.]),
+                &["source"],
+            )
+            .unwrap(),
+            cache_lookups: IntCounterVec::new(
+                Opts::new("ireina_cache_lookups_total", "Source cache lookups"),
+                &["source", "result"],
+            )
+            .unwrap(),
+            prices: GaugeVec::new(
+                Opts::new("ireina_price_usd", "Last aggregated price"),
+                &["ticker"],
+            )
+            .unwrap(),
+            commands: IntCounterVec::new(
+                Opts::new("ireina_commands_total", "Telegram commands handled"),
+                &["command"],
+            )
+            .unwrap(),
+            coinbase_polls: IntCounterVec::new(
+                Opts::new("ireina_coinbase_polls_total", "Coinbase product list polls"),
+                &["result"],
+            )
+            .unwrap(),
+            coinbase_products: IntGauge::new(
+                "ireina_coinbase_products",
+                "Products in the last Coinbase product list",
+            )
+            .unwrap(),
+            registry,
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 8] = [
+            Box::new(metrics.source_requests.clone()),
+            Box::new(metrics.source_errors.clone()),
+            Box::new(metrics.source_latency.clone()),
+            Box::new(metrics.cache_lookups.clone()),
+            Box::new(metrics.prices.clone()),
+            Box::new(metrics.commands.clone()),
+            Box::new(metrics.coinbase_polls.clone()),
+            Box::new(metrics.coinbase_products.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::{Metrics, METRICS};
+    use crate::datasources::{
+        ErrorKind, HealthRegistry, MonitoredSource, SourceError, TickerData, TickerDataSource,
+    };
+
+    #[test]
+    fn renders_text_format() {
+        let metrics = Metrics::new();
+        metrics
+            .source_requests
+            .with_label_values(&["binance:*"])
+            .inc_by(2);
+        metrics
+            .source_latency
+            .with_label_values(&["binance:*"])
+            .observe(0.2);
+        metrics.coinbase_products.set(42);
+        let text = metrics.render();
+        for line in [
+            "# TYPE ireina_source_requests_total counter",
+            "ireina_source_requests_total{source=\"binance:*\"} 2",
+            "ireina_source_latency_seconds_count{source=\"binance:*\"} 1",
+            "ireina_coinbase_products 42",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "no {:?} in\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    struct TimingOut;
+
+    #[async_trait]
+    impl TickerDataSource for TimingOut {
+        async fn get_ticker_data(&self) -> TickerData {
+            TickerData::error(SourceError::new(
+                self.name(),
+                ErrorKind::Timeout,
+                "timed out".to_owned(),
+            ))
+        }
+
+        fn name(&self) -> String {
+            "metrics-test:XYZ".to_owned()
+        }
+    }
+
+    #[tokio::test]
+    async fn monitored_sources_count_under_their_label() {
+        let health = Arc::new(HealthRegistry::new());
+        let label = "metrics-test:*";
+        let source = MonitoredSource::new(Box::new(TimingOut), health, label.to_owned());
+        source.get_ticker_data().await;
+        source.get_ticker_data().await;
+        assert_eq!(METRICS.source_requests.with_label_values(&[label]).get(), 2);
+        assert_eq!(
+            METRICS
+                .source_errors
+                .with_label_values(&[label, "timeout"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            METRICS
+                .source_latency
+                .with_label_values(&[label])
+                .get_sample_count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use pretty_duration::pretty_duration;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::resolver::Resolver;
+use crate::series::{PriceSeries, RETENTION};
+use crate::storage::Storage;
+
+/// Most move alerts a single chat may keep.
+const MAX_MOVE_ALERTS_PER_CHAT: usize = 20;
+
+const USAGE: &str = "Usage: /movealert <ticker> <percent>% <window> [cooldown] \
+                     (e.g. /movealert ETH 5% 1h) | /movealert list | /movealert del <id>";
+
+/// Fires when a ticker moves more than `percent` within `window`, at most
+/// once per `cooldown`.
+#[derive(Debug, Clone)]
+pub struct MoveAlert {
+    pub id: i64,
+    pub chat_id: i64,
+    pub ticker: String,
+    pub percent: Decimal,
+    pub window: Duration,
+    pub cooldown: Duration,
+    pub last_triggered: Option<SystemTime>,
+}
+
+impl MoveAlert {
+    fn describe(&self) -> String {
+        format!(
+            "#{} {} {}% within {} (cooldown {})",
+            self.id,
+            self.ticker,
+            self.percent,
+            pretty_duration(&self.window, None),
+            pretty_duration(&self.cooldown, None)
+        )
+    }
+
+    /// The move to report, if the alert should fire now.
+    pub fn triggered(&self, series: &PriceSeries, now: SystemTime) -> Option<f64> {
+        if let Some(last) = self.last_triggered {
+            if now.duration_since(last).unwrap_or_default() < self.cooldown {
+                return None;
+            }
+        }
+        let change = series.change_within(&self.ticker, self.window, now)?;
+        if change.abs() >= self.percent.to_f64()? {
+            Some(change)
+        } else {
+            None
+        }
+    }
+}
+
+/// Handles `/movealert` for a chat and returns the reply.
+pub async fn handle_command(
+    args: &str,
+    chat_id: i64,
+    storage: &Storage,
+    resolver: &Resolver,
+) -> Result<String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    match args.as_slice() {
+        [] | ["list"] => {
+            let alerts = storage.move_alerts(chat_id)?;
+            if alerts.is_empty() {
+                Ok("No move alerts".to_owned())
+            } else {
+                Ok(alerts
+                    .iter()
+                    .map(|a| a.describe())
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+        }
+        ["del", id] => {
+            let id = match id.trim_start_matches('#').parse() {
+                Ok(id) => id,
+                Err(_) => return Ok(USAGE.to_owned()),
+            };
+            if storage.delete_move_alert(chat_id, id)? {
+                Ok(format!("Deleted move alert #{}", id))
+            } else {
+                Ok(format!("No move alert #{}", id))
+            }
+        }
+        [ticker, percent, window, rest @ ..] => {
+            let percent = match Decimal::from_str(percent.trim_end_matches('%')) {
+                Ok(percent) if percent > Decimal::ZERO => percent,
+                _ => return Ok(USAGE.to_owned()),
+            };
+            let window = match parse_duration(window) {
+                Some(window) if window <= RETENTION => window,
+                Some(_) => {
+                    return Ok(format!(
+                        "Window is limited to {}",
+                        pretty_duration(&RETENTION, None)
+                    ))
+                }
+                None => return Ok(USAGE.to_owned()),
+            };
+            let cooldown = match rest {
+                [] => window,
+                [cooldown] => match parse_duration(cooldown) {
+                    Some(cooldown) => cooldown,
+                    None => return Ok(USAGE.to_owned()),
+                },
+                _ => return Ok(USAGE.to_owned()),
+            };
+            let ticker = ticker.to_ascii_uppercase();
+            if resolver.resolve(&ticker).await.is_none() {
+                return Ok(format!("Unknown ticker: {}", ticker));
+            }
+            if storage.move_alerts(chat_id)?.len() >= MAX_MOVE_ALERTS_PER_CHAT {
+                return Ok(format!(
+                    "Move alerts are limited to {} per chat",
+                    MAX_MOVE_ALERTS_PER_CHAT
+                ));
+            }
+            let alert = storage.add_move_alert(chat_id, &ticker, percent, window, cooldown)?;
+            Ok(format!("Added move alert {}", alert.describe()))
+        }
+        _ => Ok(USAGE.to_owned()),
+    }
+}
+
+/// Parses durations like `30m`, `1h` or `2d`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let value: u64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    let secs = value.checked_mul(match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 3600 * 24,
+        _ => return None,
+    })?;
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_duration;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172800)));
+    }
+
+    #[test]
+    fn bad_durations() {
+        for s in [
+            "",
+            "h",
+            "0h",
+            "1",
+            "1w",
+            "-1h",
+            "1.5h",
+            "1 h",
+            "99999999999999999d",
+        ] {
+            assert_eq!(parse_duration(s), None, "{:?}", s);
+        }
+    }
+}
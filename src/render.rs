@@ -0,0 +1,350 @@
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardMarkup, MessageId, ParseMode, ReplyParameters},
+    Bot, RequestError,
+};
+use unicode_width::UnicodeWidthStr;
+
+/// Telegram's limit on the length of a message, in UTF-16 code units of the
+/// text after entities are parsed.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// The markup `Document::render` produces and messages are sent in.
+pub const PARSE_MODE: ParseMode = ParseMode::MarkdownV2;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Inside code blocks MarkdownV2 only needs backticks and backslashes
+/// escaped.
+fn escape_pre(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+/// A message built from plain text, so that whatever it quotes is escaped
+/// and it can be split where Telegram's length limit requires.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+enum Block {
+    Text(String),
+    Bold(String),
+    /// Preformatted lines, shown in a monospace block.
+    Pre(Vec<String>),
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document::default()
+    }
+
+    /// A document of a single line of text.
+    pub fn text(text: impl Into<String>) -> Document {
+        let mut document = Document::new();
+        document.line(text);
+        document
+    }
+
+    pub fn line(&mut self, text: impl Into<String>) -> &mut Document {
+        self.blocks.push(Block::Text(text.into()));
+        self
+    }
+
+    pub fn bold(&mut self, text: impl Into<String>) -> &mut Document {
+        self.blocks.push(Block::Bold(text.into()));
+        self
+    }
+
+    pub fn pre<S: Into<String>>(&mut self, lines: impl IntoIterator<Item = S>) -> &mut Document {
+        self.blocks.push(Block::Pre(
+            lines.into_iter().map(|line| line.into()).collect(),
+        ));
+        self
+    }
+
+    pub fn table(&mut self, table: &Table) -> &mut Document {
+        self.pre(table.lines())
+    }
+
+    /// The document as messages of at most `MAX_MESSAGE_LEN`. Blocks are
+    /// kept whole where they fit in a message, and otherwise split between
+    /// lines.
+    pub fn render(&self) -> Vec<String> {
+        let mut messages = vec![];
+        let mut blocks: Vec<Block> = vec![];
+        let mut len = 0;
+        for block in &self.blocks {
+            let mut block = block.clone();
+            loop {
+                let room = if blocks.is_empty() {
+                    MAX_MESSAGE_LEN
+                } else {
+                    MAX_MESSAGE_LEN.saturating_sub(len + 1)
+                };
+                if block.len() <= room {
+                    len = MAX_MESSAGE_LEN - room + block.len();
+                    blocks.push(block);
+                    break;
+                }
+                if block.len() > MAX_MESSAGE_LEN || blocks.is_empty() {
+                    if let Some((head, rest)) = block.split_off(room) {
+                        blocks.push(head);
+                        block = rest;
+                    }
+                }
+                messages.push(render_blocks(&blocks));
+                blocks.clear();
+                len = 0;
+            }
+        }
+        if !blocks.is_empty() {
+            messages.push(render_blocks(&blocks));
+        }
+        messages
+    }
+}
+
+fn render_blocks(blocks: &[Block]) -> String {
+    blocks
+        .iter()
+        .map(|block| match block {
+            Block::Text(text) => escape(text),
+            Block::Bold(text) => format!("*{}*", escape(text)),
+            Block::Pre(lines) => format!("```\n{}\n```", escape_pre(&lines.join("\n"))),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Splits `text` after at most `max` UTF-16 code units.
+fn cut(text: &str, max: usize) -> (String, String) {
+    let mut len = 0;
+    for (i, c) in text.char_indices() {
+        len += c.len_utf16();
+        if len > max {
+            return (text[..i].to_owned(), text[i..].to_owned());
+        }
+    }
+    (text.to_owned(), String::new())
+}
+
+impl Block {
+    /// Length of the text as shown, which is what Telegram limits.
+    fn len(&self) -> usize {
+        match self {
+            Block::Text(text) | Block::Bold(text) => utf16_len(text),
+            // The line break after the opening backticks.
+            Block::Pre(lines) => lines.iter().map(|line| utf16_len(line) + 1).sum(),
+        }
+    }
+
+    /// Splits off as much of the block as fits in `room`, if anything
+    /// does. Preformatted lines are only cut if they wouldn't fit in a
+    /// message of their own.
+    fn split_off(&self, room: usize) -> Option<(Block, Block)> {
+        match self {
+            Block::Text(text) | Block::Bold(text) => {
+                let (head, rest) = cut(text, room);
+                if head.is_empty() {
+                    return None;
+                }
+                Some(match self {
+                    Block::Text(_) => (Block::Text(head), Block::Text(rest)),
+                    _ => (Block::Bold(head), Block::Bold(rest)),
+                })
+            }
+            Block::Pre(lines) => {
+                let mut len = 0;
+                let fitting = lines
+                    .iter()
+                    .take_while(|line| {
+                        len += utf16_len(line) + 1;
+                        len <= room
+                    })
+                    .count();
+                if fitting > 0 {
+                    return Some((
+                        Block::Pre(lines[..fitting].to_vec()),
+                        Block::Pre(lines[fitting..].to_vec()),
+                    ));
+                }
+                if room < 2 || utf16_len(&lines[0]) < MAX_MESSAGE_LEN {
+                    return None;
+                }
+                let (head, rest) = cut(&lines[0], room - 1);
+                let mut rest_lines = vec![rest];
+                rest_lines.extend_from_slice(&lines[1..]);
+                Some((Block::Pre(vec![head]), Block::Pre(rest_lines)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Columns of text padded to line up in a monospace font, measured by how
+/// wide they display rather than by bytes.
+#[derive(Debug, Clone)]
+pub struct Table {
+    align: Vec<Align>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(align: &[Align]) -> Table {
+        Table {
+            align: align.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    /// Adds a row; missing cells are left blank.
+    pub fn row<S: Into<String>>(&mut self, cells: impl IntoIterator<Item = S>) -> &mut Table {
+        self.rows
+            .push(cells.into_iter().map(|cell| cell.into()).collect());
+        self
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let widths: Vec<_> = (0..self.align.len())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.width())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (column, (align, width)) in self.align.iter().zip(&widths).enumerate() {
+                    let cell = row.get(column).map(|cell| cell.as_str()).unwrap_or("");
+                    let padding = " ".repeat(width - cell.width());
+                    if column > 0 {
+                        line.push(' ');
+                    }
+                    match align {
+                        Align::Left => {
+                            line.push_str(cell);
+                            line.push_str(&padding);
+                        }
+                        Align::Right => {
+                            line.push_str(&padding);
+                            line.push_str(cell);
+                        }
+                    }
+                }
+                line.trim_end().to_owned()
+            })
+            .collect()
+    }
+}
+
+/// Sends `document` to `chat` in as many messages as it takes. The first
+/// replies to `reply_to` and the last carries `markup`.
+pub async fn send(
+    bot: &Bot,
+    chat: ChatId,
+    document: &Document,
+    reply_to: Option<MessageId>,
+    markup: Option<InlineKeyboardMarkup>,
+) -> Result<(), RequestError> {
+    let messages = document.render();
+    let count = messages.len();
+    for (i, text) in messages.into_iter().enumerate() {
+        let mut request = bot.send_message(chat, text).parse_mode(PARSE_MODE);
+        if let (0, Some(reply_to)) = (i, reply_to) {
+            request = request.reply_parameters(ReplyParameters::new(reply_to));
+        }
+        if let (true, Some(markup)) = (i + 1 == count, &markup) {
+            request = request.reply_markup(markup.clone());
+        }
+        request.await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Align, Document, Table, MAX_MESSAGE_LEN};
+
+    #[test]
+    fn escapes_markdown_v2() {
+        let mut document = Document::new();
+        document
+            .bold("Prices in EUR")
+            .line("binance:BTC_USDT (1.5% from median) [x]!")
+            .pre(["a `b` \\ *c*"]);
+        assert_eq!(
+            document.render(),
+            ["*Prices in EUR*\nbinance:BTC\\_USDT \\(1\\.5% from median\\) \\[x\\]\\!\n```\na \\`b\\` \\\\ *c*\n```"]
+        );
+    }
+
+    #[test]
+    fn splits_long_documents() {
+        let mut document = Document::new();
+        document.line("header");
+        document.pre((0..1000).map(|i| format!("line {:>4}", i)));
+        let messages = document.render();
+        assert!(messages.len() > 1);
+        for message in &messages {
+            let shown = message.replace("```\n", "").replace("\n```", "");
+            assert!(shown.chars().count() <= MAX_MESSAGE_LEN);
+            assert!(message.ends_with("\n```"), "{}", message);
+        }
+        assert!(messages[0].starts_with("header\n```\nline    0\n"));
+        assert!(messages.last().unwrap().ends_with("line  999\n```"));
+        let lines: usize = messages.iter().map(|m| m.matches("line ").count()).sum();
+        assert_eq!(lines, 1000);
+    }
+
+    #[test]
+    fn cuts_overlong_lines() {
+        let document = Document::text("€".repeat(MAX_MESSAGE_LEN + 10));
+        let messages = document.render();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|m| m.chars().count())
+                .collect::<Vec<_>>(),
+            [MAX_MESSAGE_LEN, 10]
+        );
+    }
+
+    #[test]
+    fn aligns_by_display_width() {
+        let mut table = Table::new(&[Align::Left, Align::Right, Align::Left]);
+        table
+            .row(["Ticker", "Price"])
+            .row(["BTC", "67432.78", " ~"])
+            .row(["比特币", "1.00"]);
+        assert_eq!(
+            table.lines(),
+            ["Ticker    Price", "BTC    67432.78  ~", "比特币     1.00"]
+        );
+    }
+}
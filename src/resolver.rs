@@ -0,0 +1,513 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use log::info;
+use reqwest::Client;
+use yahoo_finance_api::YahooConnector;
+
+use crate::candles::{self, Candle, Interval};
+use crate::config::{Config, Endpoints, SourceConfig};
+use crate::datasources::{
+    AggregationRules, Aggregator, BinanceTickerDataSource, CachePolicy, CachedSource,
+    CoinbaseTickerDataSource, GoldpriceTickerDataSource, HealthRegistry, KrakenTickerDataSource,
+    MonitoredSource, ResiliencePolicy, ResilientSource, TickerDataSource,
+    YahooFinanceTickerDataSource,
+};
+use crate::symbols::{AssetPair, Exchange, SymbolMap};
+
+/// A concrete source with its native symbol worked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceSpec {
+    Exchange(Exchange, String),
+    Yahoo(String),
+    Goldprice { metal: String, currency: String },
+}
+
+/// Formatted the same way as `TickerData::sources`, e.g. `binance:BTCUSDT`.
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceSpec::Exchange(exchange, symbol) => write!(f, "{}:{}", exchange, symbol),
+            SourceSpec::Yahoo(symbol) => write!(f, "yahoo:{}", symbol),
+            SourceSpec::Goldprice { metal, currency } => {
+                write!(f, "goldprice:{}/{}", metal, currency)
+            }
+        }
+    }
+}
+
+/// How long a name that resolved is used before it is probed again, so an
+/// exchange that was down at the time gets another chance.
+const RESOLVED_TTL: Duration = Duration::from_secs(3600);
+
+/// How long a name that didn't resolve is remembered.
+const UNRESOLVED_TTL: Duration = Duration::from_secs(600);
+
+/// Most probed names kept; the least recently used go first.
+const MAX_PROBED: usize = 1000;
+
+impl SourceSpec {
+    /// The upstream API, e.g. `binance` or `yahoo`.
+    fn upstream(&self) -> String {
+        match self {
+            SourceSpec::Exchange(exchange, _) => exchange.to_string(),
+            SourceSpec::Yahoo(_) => "yahoo".to_owned(),
+            SourceSpec::Goldprice { .. } => "goldprice".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Resolved {
+    source: Arc<dyn TickerDataSource>,
+    specs: Vec<SourceSpec>,
+    pair: Option<AssetPair>,
+}
+
+/// The outcome of probing a name.
+struct Probed {
+    resolved: Option<Resolved>,
+    probed_at: Instant,
+    used_at: Instant,
+}
+
+/// Maps user-facing ticker names to data sources. Configured tickers are
+/// served as-is; anything else is read as a canonical pair, probed against
+/// the crypto exchanges listing it and then Yahoo Finance, and the working
+/// sources are cached by name for a while. Names that don't resolve are
+/// remembered too.
+pub struct Resolver {
+    client: Arc<Client>,
+    endpoints: Endpoints,
+    /// `None` while replaying a cassette.
+    yfi: Option<Arc<YahooConnector>>,
+    symbols: Arc<SymbolMap>,
+    configured: HashMap<String, Resolved>,
+    /// Rules for aggregating tickers found by probing.
+    rules: AggregationRules,
+    cache_policy: CachePolicy,
+    resilience_policy: ResiliencePolicy,
+    health: Arc<HealthRegistry>,
+    cache: Mutex<HashMap<String, Probed>>,
+    /// Held while a name is probed; concurrent lookups of the name queue on
+    /// it rather than probing again.
+    probing: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Resolver {
+    pub fn new(
+        config: &Config,
+        client: Arc<Client>,
+        yfi: Option<Arc<YahooConnector>>,
+        symbols: Arc<SymbolMap>,
+    ) -> Result<Resolver> {
+        let mut resolver = Resolver {
+            client,
+            endpoints: config.endpoints.clone(),
+            yfi,
+            symbols,
+            configured: HashMap::new(),
+            rules: config.aggregation.rules(),
+            cache_policy: config.cache.policy(),
+            resilience_policy: config.resilience.policy(),
+            health: Arc::new(HealthRegistry::new()),
+            cache: Mutex::new(HashMap::new()),
+            probing: Mutex::new(HashMap::new()),
+        };
+        for (i, ticker) in config.tickers.iter().enumerate() {
+            let pair = ticker.pair.as_ref().map(|p| p.parse()).transpose()?;
+            let specs = ticker
+                .sources
+                .iter()
+                .enumerate()
+                .map(|(j, source)| {
+                    resolver.source_spec(source, pair.as_ref()).map_err(|e| {
+                        anyhow!("tickers[{}] ({}): sources[{}]: {}", i, ticker.name, j, e)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let sources = specs
+                .into_iter()
+                .map(|spec| {
+                    let source = resolver.build(&spec, true);
+                    (spec, source)
+                })
+                .collect();
+            let rules = ticker
+                .aggregation
+                .as_ref()
+                .unwrap_or(&config.aggregation)
+                .rules();
+            let resolved = combine(sources, pair, rules);
+            resolver
+                .configured
+                .insert(ticker.name.to_ascii_uppercase(), resolved);
+        }
+        Ok(resolver)
+    }
+
+    pub async fn resolve(&self, name: &str) -> Option<Arc<dyn TickerDataSource>> {
+        Some(self.lookup(name).await?.source)
+    }
+
+    /// The underlying sources of a ticker, for callers that need more than
+    /// the current price (e.g. historical candles).
+    pub async fn specs(&self, name: &str) -> Option<Vec<SourceSpec>> {
+        Some(self.lookup(name).await?.specs)
+    }
+
+    /// The canonical pair a ticker trades as, if it is an exchange pair.
+    pub async fn pair(&self, name: &str) -> Option<AssetPair> {
+        self.lookup(name).await?.pair
+    }
+
+    /// Fetches historical candles through the same client and connector
+    /// as the live sources.
+    pub async fn candles(
+        &self,
+        spec: &SourceSpec,
+        interval: Interval,
+        count: usize,
+    ) -> Result<Vec<Candle>> {
+        candles::fetch(
+            &self.client,
+            &self.endpoints,
+            self.yfi.as_deref(),
+            spec,
+            interval,
+            count,
+        )
+        .await
+    }
+
+    /// How the upstream sources behind every resolved ticker have been
+    /// doing.
+    pub fn health(&self) -> &HealthRegistry {
+        &self.health
+    }
+
+    async fn lookup(&self, name: &str) -> Option<Resolved> {
+        let name = name.to_ascii_uppercase();
+        if let Some(resolved) = self.configured.get(&name) {
+            return Some(resolved.clone());
+        }
+        if let Some(resolved) = self.cached(&name) {
+            return resolved;
+        }
+        let probing = self
+            .probing
+            .lock()
+            .unwrap()
+            .entry(name.clone())
+            .or_default()
+            .clone();
+        let _probing = probing.lock().await;
+        // Whoever held the lock before may have just probed.
+        if let Some(resolved) = self.cached(&name) {
+            return resolved;
+        }
+        let resolved = self.probe(&name).await;
+        let resolved = self.store(&name, resolved);
+        self.probing.lock().unwrap().remove(&name);
+        resolved
+    }
+
+    /// What probing `name` found, unless it is due to be probed again.
+    fn cached(&self, name: &str) -> Option<Option<Resolved>> {
+        let mut cache = self.cache.lock().unwrap();
+        let probed = cache.get_mut(name)?;
+        let ttl = if probed.resolved.is_some() {
+            RESOLVED_TTL
+        } else {
+            UNRESOLVED_TTL
+        };
+        if probed.probed_at.elapsed() >= ttl {
+            return None;
+        }
+        probed.used_at = Instant::now();
+        Some(probed.resolved.clone())
+    }
+
+    fn store(&self, name: &str, resolved: Option<Resolved>) -> Option<Resolved> {
+        let mut cache = self.cache.lock().unwrap();
+        // A name that resolved before stays resolved if every source is
+        // failing when it is probed again.
+        let resolved = resolved.or_else(|| cache.get(name).and_then(|p| p.resolved.clone()));
+        let now = Instant::now();
+        cache.insert(
+            name.to_owned(),
+            Probed {
+                resolved: resolved.clone(),
+                probed_at: now,
+                used_at: now,
+            },
+        );
+        if cache.len() > MAX_PROBED {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, probed)| probed.used_at)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        // Forget the health of sources no ticker uses any more.
+        let used: HashSet<String> = self
+            .configured
+            .values()
+            .chain(cache.values().filter_map(|probed| probed.resolved.as_ref()))
+            .flat_map(|resolved| resolved.specs.iter().map(|spec| spec.to_string()))
+            .collect();
+        self.health.retain(|name| used.contains(name));
+        resolved
+    }
+
+    async fn probe(&self, name: &str) -> Option<Resolved> {
+        info!("Resolving ticker {}", name);
+        let pair: AssetPair = name.parse().ok()?;
+        let candidates = Exchange::ALL
+            .iter()
+            .filter_map(|exchange| {
+                let symbol = self.symbols.native_symbol(*exchange, &pair)?;
+                Some(SourceSpec::Exchange(*exchange, symbol))
+            })
+            .collect();
+        let crypto = self.probe_specs(candidates).await;
+        if !crypto.is_empty() {
+            return Some(combine(crypto, Some(pair), self.rules.clone()));
+        }
+        if name.contains('/') {
+            return None;
+        }
+        let equity = self
+            .probe_specs(vec![SourceSpec::Yahoo(name.to_owned())])
+            .await;
+        if !equity.is_empty() {
+            return Some(combine(equity, None, self.rules.clone()));
+        }
+        None
+    }
+
+    /// Keeps the specs whose sources currently return a price. They are
+    /// probed bare, so names that don't work out leave no trace in the
+    /// health report.
+    async fn probe_specs(
+        &self,
+        specs: Vec<SourceSpec>,
+    ) -> Vec<(SourceSpec, Box<dyn TickerDataSource + Sync>)> {
+        let sources: Vec<_> = specs.iter().map(|spec| self.leaf(spec)).collect();
+        let results = join_all(sources.iter().map(|s| s.get_ticker_data())).await;
+        specs
+            .into_iter()
+            .zip(results)
+            .filter(|(_, ticker_data)| ticker_data.last_price.is_some())
+            .map(|(spec, _)| {
+                let source = self.build(&spec, false);
+                (spec, source)
+            })
+            .collect()
+    }
+
+    fn source_spec(&self, source: &SourceConfig, pair: Option<&AssetPair>) -> Result<SourceSpec> {
+        let (exchange, symbol) = match source {
+            SourceConfig::Binance { symbol } => (Exchange::Binance, symbol),
+            SourceConfig::Coinbase { symbol } => (Exchange::Coinbase, symbol),
+            SourceConfig::Kraken { symbol } => (Exchange::Kraken, symbol),
+            SourceConfig::Yahoo { symbol } => return Ok(SourceSpec::Yahoo(symbol.clone())),
+            SourceConfig::Goldprice { metal, currency } => {
+                return Ok(SourceSpec::Goldprice {
+                    metal: metal.clone(),
+                    currency: currency.clone(),
+                })
+            }
+        };
+        let symbol = match (symbol, pair) {
+            (Some(symbol), _) => symbol.clone(),
+            (None, Some(pair)) => self.symbols.native_symbol(exchange, pair).ok_or(anyhow!(
+                "{} does not list {}",
+                exchange,
+                pair
+            ))?,
+            (None, None) => return Err(anyhow!("no symbol or pair given")),
+        };
+        Ok(SourceSpec::Exchange(exchange, symbol))
+    }
+
+    /// The spec's source with caching, retries and health tracking. Only
+    /// configured sources get metrics of their own; the rest are named by
+    /// users, so they share one label per upstream, e.g. `binance:*`.
+    fn build(&self, spec: &SourceSpec, configured: bool) -> Box<dyn TickerDataSource + Sync> {
+        let label = if configured {
+            spec.to_string()
+        } else {
+            format!("{}:*", spec.upstream())
+        };
+        let source = MonitoredSource::new(self.leaf(spec), self.health.clone(), label.clone());
+        let source = ResilientSource::new(Box::new(source), self.resilience_policy.clone());
+        Box::new(CachedSource::new(
+            Box::new(source),
+            self.cache_policy.clone(),
+            label,
+        ))
+    }
+
+    fn leaf(&self, spec: &SourceSpec) -> Box<dyn TickerDataSource + Sync> {
+        match spec {
+            SourceSpec::Exchange(Exchange::Binance, symbol) => {
+                Box::new(BinanceTickerDataSource::new(
+                    self.client.clone(),
+                    self.endpoints.binance.clone(),
+                    symbol.clone(),
+                ))
+            }
+            SourceSpec::Exchange(Exchange::Coinbase, symbol) => {
+                Box::new(CoinbaseTickerDataSource::new(
+                    self.client.clone(),
+                    self.endpoints.coinbase.clone(),
+                    symbol.clone(),
+                ))
+            }
+            SourceSpec::Exchange(Exchange::Kraken, symbol) => {
+                Box::new(KrakenTickerDataSource::new(
+                    self.client.clone(),
+                    self.endpoints.kraken.clone(),
+                    symbol.clone(),
+                ))
+            }
+            SourceSpec::Yahoo(symbol) => Box::new(YahooFinanceTickerDataSource::new(
+                self.yfi.clone(),
+                symbol.clone(),
+            )),
+            SourceSpec::Goldprice { metal, currency } => Box::new(GoldpriceTickerDataSource::new(
+                self.client.clone(),
+                self.endpoints.goldprice.clone(),
+                metal.clone(),
+                currency.clone(),
+            )),
+        }
+    }
+}
+
+fn combine(
+    sources: Vec<(SourceSpec, Box<dyn TickerDataSource + Sync>)>,
+    pair: Option<AssetPair>,
+    rules: AggregationRules,
+) -> Resolved {
+    let (specs, mut sources): (Vec<_>, Vec<_>) = sources.into_iter().unzip();
+    // A lone source needs no aggregating unless a quorum could fail it.
+    let source: Arc<dyn TickerDataSource> = if sources.len() == 1 && rules.quorum <= 1 {
+        let source: Arc<dyn TickerDataSource + Sync> = Arc::from(sources.remove(0));
+        source
+    } else {
+        Arc::new(Aggregator::new(sources, rules))
+    };
+    Resolved {
+        source,
+        specs,
+        pair,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use futures::future::join_all;
+    use wiremock::MockServer;
+
+    use super::{Resolver, SourceSpec, UNRESOLVED_TTL};
+    use crate::symbols::{AssetPair, Exchange};
+    use crate::test_util::{self, serve_binance};
+
+    fn resolver(server: &MockServer) -> Resolver {
+        test_util::resolver(&server.uri(), "")
+    }
+
+    async fn requests(server: &MockServer) -> usize {
+        server.received_requests().await.unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn probes_exchanges() {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        let resolver = resolver(&server);
+        assert!(resolver.resolve("btc/usd").await.is_some());
+        assert_eq!(
+            resolver.specs("BTC/USD").await.unwrap(),
+            [SourceSpec::Exchange(
+                Exchange::Binance,
+                "BTCUSDT".to_owned()
+            )]
+        );
+        assert_eq!(
+            resolver.pair("BTC/USD").await,
+            Some(AssetPair::new("BTC", "USD"))
+        );
+        // Binance, Coinbase's stats and ticker, and Kraken, once.
+        assert_eq!(requests(&server).await, 4);
+        // Probes don't show up as sources until they are used.
+        assert!(resolver.health().report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remembers_unresolved_names() {
+        let server = MockServer::start().await;
+        let resolver = resolver(&server);
+        assert!(resolver.resolve("FOO/BAR").await.is_none());
+        assert_eq!(requests(&server).await, 4);
+        assert!(resolver.resolve("foo/bar").await.is_none());
+        assert_eq!(requests(&server).await, 4);
+
+        let expired = Instant::now() - UNRESOLVED_TTL;
+        resolver
+            .cache
+            .lock()
+            .unwrap()
+            .get_mut("FOO/BAR")
+            .unwrap()
+            .probed_at = expired;
+        assert!(resolver.resolve("FOO/BAR").await.is_none());
+        assert_eq!(requests(&server).await, 8);
+    }
+
+    #[tokio::test]
+    async fn health_of_used_sources_only() {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        let resolver = resolver(&server);
+        let source = resolver.resolve("BTC/USD").await.unwrap();
+        assert!(source.get_ticker_data().await.errors.is_empty());
+        // By source rather than metrics label, and without the exchanges
+        // that didn't list the pair.
+        let names: Vec<_> = resolver
+            .health()
+            .report()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["binance:BTCUSDT"]);
+
+        // Evicted along with the name.
+        resolver.cache.lock().unwrap().clear();
+        assert!(resolver.resolve("FOO/BAR").await.is_none());
+        assert!(resolver.health().report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_one_probe() {
+        let server = MockServer::start().await;
+        serve_binance(&server).await;
+        let resolver = resolver(&server);
+        let resolved = join_all((0..5).map(|_| resolver.resolve("BTC/USD"))).await;
+        assert!(resolved.iter().all(Option::is_some));
+        assert_eq!(requests(&server).await, 4);
+        assert!(resolver.probing.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::error;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::storage::Storage;
+
+/// How long observed prices are kept, which bounds the longest window a
+/// move alert can use.
+pub const RETENTION: Duration = Duration::from_secs(3600 * 24);
+
+/// Samples closer together than this are dropped, so frequent queries
+/// don't grow the series without bound.
+const MIN_SPACING: Duration = Duration::from_secs(10);
+
+/// Most tickers kept at once, as any ticker a user queries is recorded.
+/// Another evicts the one recorded least recently.
+const MAX_TICKERS: usize = 1000;
+
+/// Rolling per-ticker series of observed prices, optionally mirrored to the
+/// database so it survives restarts.
+pub struct PriceSeries {
+    samples: Mutex<HashMap<String, VecDeque<(SystemTime, Decimal)>>>,
+    storage: Option<Arc<Storage>>,
+}
+
+impl PriceSeries {
+    pub fn new(storage: Option<Arc<Storage>>) -> Result<PriceSeries> {
+        let mut samples: HashMap<_, VecDeque<_>> = HashMap::new();
+        if let Some(storage) = &storage {
+            storage.prune_price_samples(SystemTime::now() - RETENTION)?;
+            for (ticker, time, price) in storage.price_samples()? {
+                samples.entry(ticker).or_default().push_back((time, price));
+            }
+        }
+        Ok(PriceSeries {
+            samples: Mutex::new(samples),
+            storage,
+        })
+    }
+
+    pub fn record(&self, ticker: &str, price: Decimal) {
+        let now = SystemTime::now();
+        {
+            let mut samples = self.samples.lock().unwrap();
+            if !samples.contains_key(ticker) && samples.len() >= MAX_TICKERS {
+                let stale = samples
+                    .iter()
+                    .min_by_key(|(_, series)| series.back().map(|(time, _)| *time))
+                    .map(|(ticker, _)| ticker.clone());
+                if let Some(stale) = stale {
+                    samples.remove(&stale);
+                }
+            }
+            let series = samples.entry(ticker.to_owned()).or_default();
+            if let Some((last, _)) = series.back() {
+                if now.duration_since(*last).unwrap_or_default() < MIN_SPACING {
+                    return;
+                }
+            }
+            series.push_back((now, price));
+            while let Some((time, _)) = series.front() {
+                if now.duration_since(*time).unwrap_or_default() <= RETENTION {
+                    break;
+                }
+                series.pop_front();
+            }
+        }
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.add_price_sample(ticker, now, price) {
+                error!("Persist price sample: {}", e);
+            }
+        }
+    }
+
+    /// The largest percentage move to the latest sample within `window`
+    /// ending at `now`, measured from the window's low or high, whichever is
+    /// further. None if the latest sample is older than the window.
+    pub fn change_within(&self, ticker: &str, window: Duration, now: SystemTime) -> Option<f64> {
+        let samples = self.samples.lock().unwrap();
+        let series = samples.get(ticker)?;
+        let (latest_time, latest) = *series.back()?;
+        let start = now.checked_sub(window)?;
+        if latest_time < start {
+            return None;
+        }
+        let in_window = series
+            .iter()
+            .filter(|(time, _)| *time >= start)
+            .map(|(_, price)| *price);
+        let low = in_window.clone().min()?;
+        let high = in_window.max()?;
+        [low, high]
+            .iter()
+            .filter(|reference| !reference.is_zero())
+            .filter_map(|reference| Some(((latest / reference).to_f64()? - 1.) * 100.))
+            .max_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap())
+    }
+
+    pub fn prune_storage(&self) {
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.prune_price_samples(SystemTime::now() - RETENTION) {
+                error!("Prune price samples: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{PriceSeries, MAX_TICKERS};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    /// A series of `prices` taken the given number of minutes before `now`.
+    fn series(now: SystemTime, prices: &[(u32, Decimal)]) -> PriceSeries {
+        let series = PriceSeries::new(None).unwrap();
+        series.samples.lock().unwrap().insert(
+            "BTC".to_owned(),
+            prices
+                .iter()
+                .map(|&(minutes, price)| (now - MINUTE * minutes, price))
+                .collect(),
+        );
+        series
+    }
+
+    #[test]
+    fn change_from_low_or_high() {
+        let now = SystemTime::now();
+        let rising = series(now, &[(50, dec!(50)), (30, dec!(100)), (1, dec!(150))]);
+        assert_eq!(rising.change_within("BTC", MINUTE * 40, now), Some(50.));
+        let falling = series(now, &[(30, dec!(90)), (20, dec!(100)), (1, dec!(75))]);
+        assert_eq!(falling.change_within("BTC", MINUTE * 60, now), Some(-25.));
+    }
+
+    #[test]
+    fn window_ends_now() {
+        let now = SystemTime::now();
+        let series = series(now, &[(90, dec!(50)), (70, dec!(100)), (50, dec!(110))]);
+        // The window reaching back from the latest sample would include 50.
+        assert_eq!(series.change_within("BTC", MINUTE * 60, now), Some(0.));
+        assert_eq!(series.change_within("BTC", MINUTE * 30, now), None);
+        assert_eq!(series.change_within("ETH", MINUTE * 60, now), None);
+    }
+
+    #[test]
+    fn evicts_least_recent_ticker() {
+        let now = SystemTime::now();
+        let series = PriceSeries::new(None).unwrap();
+        series
+            .samples
+            .lock()
+            .unwrap()
+            .extend((0..MAX_TICKERS).map(|i| {
+                let time = now - MINUTE * (MAX_TICKERS - i) as u32;
+                (format!("T{}", i), vec![(time, dec!(1))].into())
+            }));
+        series.record("T1", dec!(2));
+        series.record("NEW", dec!(1));
+        let samples = series.samples.lock().unwrap();
+        assert_eq!(samples.len(), MAX_TICKERS);
+        assert!(!samples.contains_key("T0"));
+        assert!(samples.contains_key("T1"));
+        assert!(samples.contains_key("NEW"));
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rust_decimal::Decimal;
+
+use crate::alerts::{Condition, PriceAlert};
+use crate::move_alerts::MoveAlert;
+use crate::subscriptions::Subscription;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS watchlist (
+    chat_id INTEGER NOT NULL,
+    ticker TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (chat_id, ticker)
+);
+CREATE TABLE IF NOT EXISTS alerts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    ticker TEXT NOT NULL,
+    condition TEXT NOT NULL,
+    threshold TEXT NOT NULL,
+    repeat INTEGER NOT NULL,
+    armed INTEGER NOT NULL DEFAULT 1
+);
+CREATE TABLE IF NOT EXISTS move_alerts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    ticker TEXT NOT NULL,
+    percent TEXT NOT NULL,
+    window_secs INTEGER NOT NULL,
+    cooldown_secs INTEGER NOT NULL,
+    last_triggered INTEGER
+);
+CREATE TABLE IF NOT EXISTS price_samples (
+    ticker TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    price TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS price_samples_time ON price_samples (time);
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    schedule TEXT NOT NULL,
+    timezone TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chat_settings (
+    chat_id INTEGER PRIMARY KEY,
+    currency TEXT
+);
+";
+
+/// Per-chat state kept in a local SQLite database. Queries are small and
+/// local, so they run inline rather than on a blocking thread.
+pub struct Storage {
+    conn: Mutex<Connection>,
+}
+
+impl Storage {
+    pub fn open(path: &str) -> Result<Storage> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Storage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn watchlist(&self, chat_id: i64) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT ticker FROM watchlist WHERE chat_id = ?1 ORDER BY position")?;
+        let tickers = stmt
+            .query_map(params![chat_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(tickers)
+    }
+
+    /// Appends tickers not yet on the watchlist, keeping their order.
+    pub fn watch_add(&self, chat_id: i64, tickers: &[String]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for ticker in tickers {
+            tx.execute(
+                "INSERT OR IGNORE INTO watchlist (chat_id, ticker, position)
+                 SELECT ?1, ?2, COALESCE(MAX(position) + 1, 0) FROM watchlist WHERE chat_id = ?1",
+                params![chat_id, ticker],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns how many of the tickers were on the watchlist.
+    pub fn watch_remove(&self, chat_id: i64, tickers: &[String]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut removed = 0;
+        for ticker in tickers {
+            removed += tx.execute(
+                "DELETE FROM watchlist WHERE chat_id = ?1 AND ticker = ?2",
+                params![chat_id, ticker],
+            )?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// The chat's display currency, if it picked one.
+    pub fn currency(&self, chat_id: i64) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let currency = conn
+            .query_row(
+                "SELECT currency FROM chat_settings WHERE chat_id = ?1",
+                params![chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(currency.flatten())
+    }
+
+    pub fn set_currency(&self, chat_id: i64, currency: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO chat_settings (chat_id, currency) VALUES (?1, ?2)
+             ON CONFLICT (chat_id) DO UPDATE SET currency = excluded.currency",
+            params![chat_id, currency],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_alert(
+        &self,
+        chat_id: i64,
+        ticker: &str,
+        condition: Condition,
+        threshold: Decimal,
+        repeat: bool,
+    ) -> Result<PriceAlert> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO alerts (chat_id, ticker, condition, threshold, repeat)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chat_id,
+                ticker,
+                condition.as_str(),
+                threshold.to_string(),
+                repeat
+            ],
+        )?;
+        Ok(PriceAlert {
+            id: conn.last_insert_rowid(),
+            chat_id,
+            ticker: ticker.to_owned(),
+            condition,
+            threshold,
+            repeat,
+            armed: true,
+        })
+    }
+
+    pub fn alerts(&self, chat_id: i64) -> Result<Vec<PriceAlert>> {
+        self.query_alerts("WHERE chat_id = ?1", params![chat_id])
+    }
+
+    pub fn all_alerts(&self) -> Result<Vec<PriceAlert>> {
+        self.query_alerts("", params![])
+    }
+
+    /// Returns whether the chat had such an alert.
+    pub fn delete_alert(&self, chat_id: i64, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM alerts WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id, id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    pub fn set_alert_armed(&self, id: i64, armed: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE alerts SET armed = ?2 WHERE id = ?1",
+            params![id, armed],
+        )?;
+        Ok(())
+    }
+
+    fn query_alerts(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<PriceAlert>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, chat_id, ticker, condition, threshold, repeat, armed FROM alerts {} ORDER BY id",
+            filter
+        ))?;
+        let rows = stmt
+            .query_map(params, |row| Ok(alert_from_row(row)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter().collect()
+    }
+
+    pub fn add_move_alert(
+        &self,
+        chat_id: i64,
+        ticker: &str,
+        percent: Decimal,
+        window: Duration,
+        cooldown: Duration,
+    ) -> Result<MoveAlert> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO move_alerts (chat_id, ticker, percent, window_secs, cooldown_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chat_id,
+                ticker,
+                percent.to_string(),
+                window.as_secs(),
+                cooldown.as_secs()
+            ],
+        )?;
+        Ok(MoveAlert {
+            id: conn.last_insert_rowid(),
+            chat_id,
+            ticker: ticker.to_owned(),
+            percent,
+            window,
+            cooldown,
+            last_triggered: None,
+        })
+    }
+
+    pub fn move_alerts(&self, chat_id: i64) -> Result<Vec<MoveAlert>> {
+        self.query_move_alerts("WHERE chat_id = ?1", params![chat_id])
+    }
+
+    pub fn all_move_alerts(&self) -> Result<Vec<MoveAlert>> {
+        self.query_move_alerts("", params![])
+    }
+
+    /// Returns whether the chat had such an alert.
+    pub fn delete_move_alert(&self, chat_id: i64, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM move_alerts WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id, id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    pub fn set_move_alert_triggered(&self, id: i64, time: SystemTime) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE move_alerts SET last_triggered = ?2 WHERE id = ?1",
+            params![id, to_unix(time)],
+        )?;
+        Ok(())
+    }
+
+    fn query_move_alerts(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<MoveAlert>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, chat_id, ticker, percent, window_secs, cooldown_secs, last_triggered
+             FROM move_alerts {} ORDER BY id",
+            filter
+        ))?;
+        let rows = stmt
+            .query_map(params, |row| Ok(move_alert_from_row(row)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter().collect()
+    }
+
+    pub fn add_price_sample(&self, ticker: &str, time: SystemTime, price: Decimal) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO price_samples (ticker, time, price) VALUES (?1, ?2, ?3)",
+            params![ticker, to_unix(time), price.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// All stored samples in time order.
+    pub fn price_samples(&self) -> Result<Vec<(String, SystemTime, Decimal)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT ticker, time, price FROM price_samples ORDER BY time")?;
+        let rows = stmt
+            .query_map(params![], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(ticker, time, price)| Ok((ticker, from_unix(time), Decimal::from_str(&price)?)))
+            .collect()
+    }
+
+    pub fn prune_price_samples(&self, before: SystemTime) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM price_samples WHERE time < ?1",
+            params![to_unix(before)],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_subscription(
+        &self,
+        chat_id: i64,
+        schedule: &str,
+        timezone: &str,
+        description: &str,
+    ) -> Result<Subscription> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO subscriptions (chat_id, schedule, timezone, description)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![chat_id, schedule, timezone, description],
+        )?;
+        Ok(Subscription {
+            id: conn.last_insert_rowid(),
+            chat_id,
+            schedule: schedule.to_owned(),
+            timezone: timezone.to_owned(),
+            description: description.to_owned(),
+        })
+    }
+
+    pub fn subscriptions(&self, chat_id: i64) -> Result<Vec<Subscription>> {
+        self.query_subscriptions("WHERE chat_id = ?1", params![chat_id])
+    }
+
+    pub fn all_subscriptions(&self) -> Result<Vec<Subscription>> {
+        self.query_subscriptions("", params![])
+    }
+
+    /// Returns whether the chat had such a subscription.
+    pub fn delete_subscription(&self, chat_id: i64, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM subscriptions WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id, id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn query_subscriptions(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Subscription>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, chat_id, schedule, timezone, description
+             FROM subscriptions {} ORDER BY id",
+            filter
+        ))?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(Subscription {
+                    id: row.get(0)?,
+                    chat_id: row.get(1)?,
+                    schedule: row.get(2)?,
+                    timezone: row.get(3)?,
+                    description: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+}
+
+pub fn to_unix(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+pub fn from_unix(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+fn alert_from_row(row: &Row) -> Result<PriceAlert> {
+    let condition: String = row.get(3)?;
+    let threshold: String = row.get(4)?;
+    Ok(PriceAlert {
+        id: row.get(0)?,
+        chat_id: row.get(1)?,
+        ticker: row.get(2)?,
+        condition: condition.parse()?,
+        threshold: Decimal::from_str(&threshold)?,
+        repeat: row.get(5)?,
+        armed: row.get(6)?,
+    })
+}
+
+fn move_alert_from_row(row: &Row) -> Result<MoveAlert> {
+    let percent: String = row.get(3)?;
+    let last_triggered: Option<i64> = row.get(6)?;
+    Ok(MoveAlert {
+        id: row.get(0)?,
+        chat_id: row.get(1)?,
+        ticker: row.get(2)?,
+        percent: Decimal::from_str(&percent)?,
+        window: Duration::from_secs(row.get(4)?),
+        cooldown: Duration::from_secs(row.get(5)?),
+        last_triggered: last_triggered.map(from_unix),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Storage;
+
+    fn tickers(tickers: &[&str]) -> Vec<String> {
+        tickers.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn watchlist_keeps_order_without_duplicates() {
+        let storage = Storage::open(":memory:").unwrap();
+        storage.watch_add(1, &tickers(&["ETH", "BTC"])).unwrap();
+        storage
+            .watch_add(1, &tickers(&["SOL", "ETH", "SOL"]))
+            .unwrap();
+        storage.watch_add(2, &tickers(&["DOGE"])).unwrap();
+        assert_eq!(storage.watchlist(1).unwrap(), ["ETH", "BTC", "SOL"]);
+        assert_eq!(storage.watchlist(2).unwrap(), ["DOGE"]);
+        assert!(storage.watchlist(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn watchlist_remove() {
+        let storage = Storage::open(":memory:").unwrap();
+        storage
+            .watch_add(1, &tickers(&["ETH", "BTC", "SOL"]))
+            .unwrap();
+        storage.watch_add(2, &tickers(&["BTC"])).unwrap();
+        assert_eq!(
+            storage.watch_remove(1, &tickers(&["BTC", "DOGE"])).unwrap(),
+            1
+        );
+        assert_eq!(storage.watchlist(1).unwrap(), ["ETH", "SOL"]);
+        assert_eq!(storage.watchlist(2).unwrap(), ["BTC"]);
+        // Appended after the last one, not into the gap.
+        storage.watch_add(1, &tickers(&["BTC"])).unwrap();
+        assert_eq!(storage.watchlist(1).unwrap(), ["ETH", "SOL", "BTC"]);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use log::{error, info};
+use teloxide::{types::ChatId, Bot};
+
+use crate::currency::chat_currency;
+use crate::render;
+use crate::storage::Storage;
+use crate::{chat_tickers, get_update, DataSources};
+
+/// Most subscriptions a single chat may keep.
+const MAX_SUBSCRIPTIONS_PER_CHAT: usize = 5;
+
+/// Schedules may not fire more often than this.
+const MIN_INTERVAL: chrono::Duration = chrono::Duration::hours(1);
+
+const USAGE: &str = "Usage: /subscribe hourly [tz] | daily <HH:MM> [tz] | weekdays <HH:MM> [tz] \
+                     | cron <min> <hour> <day> <month> <weekday> [tz] | list\n\
+                     /unsubscribe <id>";
+
+/// A chat's scheduled price digest. `schedule` is a cron expression with a
+/// leading seconds field, evaluated in `timezone`.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub id: i64,
+    pub chat_id: i64,
+    pub schedule: String,
+    pub timezone: String,
+    pub description: String,
+}
+
+impl Subscription {
+    fn describe(&self) -> String {
+        format!("#{} {}", self.id, self.description)
+    }
+
+    fn schedule(&self) -> Result<(Schedule, Tz)> {
+        let schedule = Schedule::from_str(&self.schedule)?;
+        let timezone = self
+            .timezone
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok((schedule, timezone))
+    }
+}
+
+/// Handles `/subscribe` for a chat and returns the reply.
+pub fn handle_subscribe(args: &str, chat_id: i64, storage: &Storage) -> Result<String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    if args.is_empty() || args == ["list"] {
+        let subscriptions = storage.subscriptions(chat_id)?;
+        if subscriptions.is_empty() {
+            return Ok(format!("No subscriptions\n{}", USAGE));
+        }
+        return Ok(subscriptions
+            .iter()
+            .map(|s| s.describe())
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+    let (schedule, timezone) = match parse_spec(&args) {
+        Ok(spec) => spec,
+        Err(e) => return Ok(format!("{}\n{}", e, USAGE)),
+    };
+    if storage.subscriptions(chat_id)?.len() >= MAX_SUBSCRIPTIONS_PER_CHAT {
+        return Ok(format!(
+            "Subscriptions are limited to {} per chat",
+            MAX_SUBSCRIPTIONS_PER_CHAT
+        ));
+    }
+    let subscription =
+        storage.add_subscription(chat_id, &schedule, &timezone.to_string(), &args.join(" "))?;
+    Ok(format!("Added subscription {}", subscription.describe()))
+}
+
+/// Handles `/unsubscribe` for a chat and returns the reply.
+pub fn handle_unsubscribe(args: &str, chat_id: i64, storage: &Storage) -> Result<String> {
+    let id = match args.trim().trim_start_matches('#').parse() {
+        Ok(id) => id,
+        Err(_) => return Ok(USAGE.to_owned()),
+    };
+    if storage.delete_subscription(chat_id, id)? {
+        Ok(format!("Deleted subscription #{}", id))
+    } else {
+        Ok(format!("No subscription #{}", id))
+    }
+}
+
+/// Turns a user schedule spec into a cron expression and time zone.
+fn parse_spec(args: &[&str]) -> std::result::Result<(String, Tz), String> {
+    let (schedule, rest) = match args {
+        ["hourly", rest @ ..] => ("0 0 * * * *".to_owned(), rest),
+        ["daily", time, rest @ ..] => {
+            let (hour, minute) = parse_time(time)?;
+            (format!("0 {} {} * * *", minute, hour), rest)
+        }
+        ["weekdays", time, rest @ ..] => {
+            let (hour, minute) = parse_time(time)?;
+            (format!("0 {} {} * * Mon-Fri", minute, hour), rest)
+        }
+        ["cron", minute, hour, day, month, weekday, rest @ ..] => (
+            format!("0 {} {} {} {} {}", minute, hour, day, month, weekday),
+            rest,
+        ),
+        _ => return Err("Unknown schedule".to_owned()),
+    };
+    let timezone = match rest {
+        [] => Tz::UTC,
+        [timezone] => timezone
+            .parse()
+            .map_err(|_| format!("Unknown time zone {}", timezone))?,
+        _ => return Err("Unknown schedule".to_owned()),
+    };
+    let parsed = Schedule::from_str(&schedule).map_err(|e| format!("Invalid schedule: {}", e))?;
+    let upcoming: Vec<_> = parsed.upcoming(timezone).take(50).collect();
+    if upcoming.is_empty() {
+        return Err("Schedule never fires".to_owned());
+    }
+    if upcoming.windows(2).any(|w| w[1] - w[0] < MIN_INTERVAL) {
+        return Err("Schedule fires more often than hourly".to_owned());
+    }
+    Ok((schedule, timezone))
+}
+
+fn parse_time(time: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("Invalid time {}, expected HH:MM", time);
+    let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    if hour >= 24 || minute >= 60 {
+        return Err(invalid());
+    }
+    Ok((hour, minute))
+}
+
+/// Sends the price table to every subscribed chat whose schedule fired
+/// since the previous tick.
+pub struct DigestScheduler {
+    bot: Bot,
+    storage: Arc<Storage>,
+    data_sources: Arc<DataSources>,
+}
+
+impl DigestScheduler {
+    pub fn new(bot: Bot, storage: Arc<Storage>, data_sources: Arc<DataSources>) -> DigestScheduler {
+        DigestScheduler {
+            bot,
+            storage,
+            data_sources,
+        }
+    }
+
+    pub async fn monitor(&self) {
+        let mut last_tick = Utc::now();
+        loop {
+            tokio::time::sleep(Duration::from_secs(20)).await;
+            let now = Utc::now();
+            if let Err(e) = self.tick(last_tick, now).await {
+                error!("Digest scheduler: {}", e);
+            }
+            last_tick = now;
+        }
+    }
+
+    async fn tick(&self, last_tick: DateTime<Utc>, now: DateTime<Utc>) -> Result<()> {
+        for subscription in self.storage.all_subscriptions()? {
+            let (schedule, timezone) = match subscription.schedule() {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    error!("Subscription #{}: {}", subscription.id, e);
+                    continue;
+                }
+            };
+            let due = schedule
+                .after(&last_tick.with_timezone(&timezone))
+                .next()
+                .is_some_and(|next| next <= now);
+            if due {
+                self.send(&subscription).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&self, subscription: &Subscription) {
+        info!("Sending digest for subscription #{}", subscription.id);
+        let tickers = chat_tickers(&self.storage, subscription.chat_id);
+        let currency = chat_currency(&self.storage, subscription.chat_id);
+        let update = match get_update(&self.data_sources, &tickers, &currency).await {
+            Ok(update) => update,
+            Err(e) => {
+                error!("get_update: {}", e);
+                return;
+            }
+        };
+        if let Err(e) =
+            render::send(&self.bot, ChatId(subscription.chat_id), &update, None, None).await
+        {
+            error!("Send digest #{}: {}", subscription.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+
+    use super::{parse_spec, parse_time};
+
+    fn spec(args: &str) -> Result<(String, Tz), String> {
+        parse_spec(&args.split_whitespace().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn schedules() {
+        assert_eq!(spec("hourly"), Ok(("0 0 * * * *".to_owned(), Tz::UTC)));
+        assert_eq!(
+            spec("daily 08:30 Europe/Berlin"),
+            Ok(("0 30 8 * * *".to_owned(), Tz::Europe__Berlin))
+        );
+        assert_eq!(
+            spec("weekdays 9:05 Asia/Tokyo"),
+            Ok(("0 5 9 * * Mon-Fri".to_owned(), Tz::Asia__Tokyo))
+        );
+        assert_eq!(
+            spec("cron 0 */6 * * Sat,Sun"),
+            Ok(("0 0 */6 * * Sat,Sun".to_owned(), Tz::UTC))
+        );
+    }
+
+    #[test]
+    fn rejected_schedules() {
+        assert_eq!(
+            spec("cron */30 * * * *"),
+            Err("Schedule fires more often than hourly".to_owned())
+        );
+        assert_eq!(
+            spec("cron 0,59 * * * *"),
+            Err("Schedule fires more often than hourly".to_owned())
+        );
+        assert_eq!(
+            spec("daily 08:30 Mars/Olympus"),
+            Err("Unknown time zone Mars/Olympus".to_owned())
+        );
+        assert!(spec("cron 0 25 * * *")
+            .unwrap_err()
+            .starts_with("Invalid schedule"));
+        for args in [
+            "",
+            "monthly",
+            "daily",
+            "hourly UTC Europe/Berlin",
+            "cron 0 * * *",
+        ] {
+            assert_eq!(spec(args), Err("Unknown schedule".to_owned()), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("00:00"), Ok((0, 0)));
+        assert_eq!(parse_time("23:59"), Ok((23, 59)));
+        for time in ["24:00", "12:60", "12", "12:3x", "-1:00", ""] {
+            assert_eq!(
+                parse_time(time),
+                Err(format!("Invalid time {}, expected HH:MM", time))
+            );
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use reqwest::Client;
+use serde_json::Value as JsonValue;
+
+use crate::config::Endpoints;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exchange {
+    Binance,
+    Coinbase,
+    Kraken,
+}
+
+impl Exchange {
+    pub const ALL: [Exchange; 3] = [Exchange::Binance, Exchange::Coinbase, Exchange::Kraken];
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exchange::Binance => write!(f, "binance"),
+            Exchange::Coinbase => write!(f, "coinbase"),
+            Exchange::Kraken => write!(f, "kraken"),
+        }
+    }
+}
+
+/// A canonical base/quote pair such as `BTC/USD`, independent of how any
+/// exchange spells it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AssetPair {
+    pub base: String,
+    pub quote: String,
+}
+
+impl AssetPair {
+    pub fn new(base: &str, quote: &str) -> AssetPair {
+        AssetPair {
+            base: canonical_asset(base),
+            quote: canonical_asset(quote),
+        }
+    }
+}
+
+impl FromStr for AssetPair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AssetPair> {
+        let (base, quote) = s.split_once('/').unwrap_or((s, "USD"));
+        if base.trim().is_empty() || quote.trim().is_empty() {
+            return Err(anyhow!("invalid asset pair {:?}", s));
+        }
+        Ok(AssetPair::new(base.trim(), quote.trim()))
+    }
+}
+
+impl fmt::Display for AssetPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+/// Translates canonical pairs into exchange-native symbols. Until an
+/// exchange's listing has been fetched, symbols are derived from that
+/// exchange's naming rules; afterwards only listed pairs are returned.
+pub struct SymbolMap {
+    client: Arc<Client>,
+    endpoints: Endpoints,
+    listings: RwLock<HashMap<Exchange, HashMap<AssetPair, String>>>,
+}
+
+impl SymbolMap {
+    pub fn new(client: Arc<Client>, endpoints: Endpoints) -> SymbolMap {
+        SymbolMap {
+            client,
+            endpoints,
+            listings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn native_symbol(&self, exchange: Exchange, pair: &AssetPair) -> Option<String> {
+        let listings = self.listings.read().unwrap();
+        match listings.get(&exchange) {
+            Some(listing) => listing.get(pair).cloned().or_else(|| {
+                // Binance quotes almost everything in USDT rather than USD.
+                if exchange == Exchange::Binance && pair.quote == "USD" {
+                    listing.get(&AssetPair::new(&pair.base, "USDT")).cloned()
+                } else {
+                    None
+                }
+            }),
+            None => Some(derive_symbol(exchange, pair)),
+        }
+    }
+
+    pub async fn monitor(&self) {
+        loop {
+            tokio::time::sleep(Duration::from_secs(3600 * 24)).await;
+            self.refresh().await;
+        }
+    }
+
+    /// Reloads every exchange's pair listing. An exchange that fails keeps
+    /// its previous listing.
+    pub async fn refresh(&self) {
+        for exchange in Exchange::ALL.iter() {
+            match self.query_listing(*exchange).await {
+                Ok(listing) => {
+                    info!("Loaded {} {} pairs", listing.len(), exchange);
+                    self.listings.write().unwrap().insert(*exchange, listing);
+                }
+                Err(e) => error!("Symbol listing: {}", e),
+            }
+        }
+    }
+
+    async fn query_listing(&self, exchange: Exchange) -> Result<HashMap<AssetPair, String>> {
+        match exchange {
+            Exchange::Binance => self.query_binance().await,
+            Exchange::Coinbase => self.query_coinbase().await,
+            Exchange::Kraken => self.query_kraken().await,
+        }
+    }
+
+    async fn query_binance(&self) -> Result<HashMap<AssetPair, String>> {
+        let response: JsonValue = self
+            .client
+            .get(format!("{}/api/v3/exchangeInfo", self.endpoints.binance))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if response["msg"] != JsonValue::Null {
+            return Err(anyhow!("Binance: {}", response["msg"]));
+        }
+        let symbols = response["symbols"]
+            .as_array()
+            .ok_or(anyhow!("Failed to parse Binance exchange info"))?;
+        Ok(symbols
+            .iter()
+            .filter(|s| s["status"] == "TRADING")
+            .filter_map(|s| {
+                Some((
+                    AssetPair::new(s["baseAsset"].as_str()?, s["quoteAsset"].as_str()?),
+                    s["symbol"].as_str()?.to_owned(),
+                ))
+            })
+            .collect())
+    }
+
+    async fn query_coinbase(&self) -> Result<HashMap<AssetPair, String>> {
+        let response: JsonValue = self
+            .client
+            .get(format!("{}/products", self.endpoints.coinbase))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if response["message"] != JsonValue::Null {
+            return Err(anyhow!("Coinbase: {}", response["message"]));
+        }
+        let products = response
+            .as_array()
+            .ok_or(anyhow!("Failed to parse Coinbase products"))?;
+        Ok(products
+            .iter()
+            .filter(|p| p["trading_disabled"] != true)
+            .filter_map(|p| {
+                Some((
+                    AssetPair::new(p["base_currency"].as_str()?, p["quote_currency"].as_str()?),
+                    p["id"].as_str()?.to_owned(),
+                ))
+            })
+            .collect())
+    }
+
+    async fn query_kraken(&self) -> Result<HashMap<AssetPair, String>> {
+        let response: JsonValue = self
+            .client
+            .get(format!("{}/0/public/AssetPairs", self.endpoints.kraken))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if response["error"][0] != JsonValue::Null {
+            return Err(anyhow!("Kraken: {}", response["error"][0]));
+        }
+        let pairs = response["result"]
+            .as_object()
+            .ok_or(anyhow!("Failed to parse Kraken asset pairs"))?;
+        // The result keys (e.g. XXBTZUSD) are what the Ticker endpoint
+        // reports prices under, so those are the native symbols.
+        Ok(pairs
+            .iter()
+            .filter_map(|(symbol, info)| {
+                let (base, quote) = info["wsname"].as_str()?.split_once('/')?;
+                Some((AssetPair::new(base, quote), symbol.clone()))
+            })
+            .collect())
+    }
+}
+
+/// Maps exchange-specific asset codes to the common ones.
+fn canonical_asset(code: &str) -> String {
+    match code.to_ascii_uppercase().as_str() {
+        "XBT" => "BTC".to_owned(),
+        "XDG" => "DOGE".to_owned(),
+        code => code.to_owned(),
+    }
+}
+
+fn derive_symbol(exchange: Exchange, pair: &AssetPair) -> String {
+    match exchange {
+        Exchange::Binance => {
+            let quote = if pair.quote == "USD" {
+                "USDT"
+            } else {
+                &pair.quote
+            };
+            format!("{}{}", pair.base, quote)
+        }
+        Exchange::Coinbase => format!("{}-{}", pair.base, pair.quote),
+        Exchange::Kraken => {
+            let base = kraken_asset(&pair.base);
+            let quote = kraken_asset(&pair.quote);
+            match (kraken_legacy_name(&base), kraken_legacy_name(&quote)) {
+                (Some(base), Some(quote)) if base.starts_with('X') => format!("{}{}", base, quote),
+                _ => format!("{}{}", base, quote),
+            }
+        }
+    }
+}
+
+fn kraken_asset(asset: &str) -> String {
+    match asset {
+        "BTC" => "XBT".to_owned(),
+        "DOGE" => "XDG".to_owned(),
+        asset => asset.to_owned(),
+    }
+}
+
+/// Assets listed before Kraken dropped the X (crypto) / Z (fiat) prefixes
+/// still use the prefixed pair names, e.g. XXBTZUSD or XETHXXBT.
+fn kraken_legacy_name(asset: &str) -> Option<String> {
+    match asset {
+        "XBT" | "ETH" | "LTC" | "XRP" | "XLM" | "ETC" | "XMR" | "ZEC" | "REP" | "MLN" => {
+            Some(format!("X{}", asset))
+        }
+        "USD" | "EUR" | "GBP" | "JPY" | "CAD" => Some(format!("Z{}", asset)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use reqwest::Client;
+
+    use super::{
+        canonical_asset, derive_symbol, kraken_legacy_name, AssetPair, Exchange, SymbolMap,
+    };
+    use crate::config::Endpoints;
+
+    fn pair(s: &str) -> AssetPair {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn canonical_assets() {
+        assert_eq!(canonical_asset("xbt"), "BTC");
+        assert_eq!(canonical_asset("XDG"), "DOGE");
+        assert_eq!(canonical_asset("eth"), "ETH");
+        assert_eq!(pair("xbt/zusd"), AssetPair::new("BTC", "ZUSD"));
+        assert_eq!(pair("ETH"), AssetPair::new("ETH", "USD"));
+    }
+
+    #[test]
+    fn kraken_legacy_names() {
+        assert_eq!(kraken_legacy_name("XBT").as_deref(), Some("XXBT"));
+        assert_eq!(kraken_legacy_name("ETH").as_deref(), Some("XETH"));
+        assert_eq!(kraken_legacy_name("USD").as_deref(), Some("ZUSD"));
+        assert_eq!(kraken_legacy_name("SOL"), None);
+        assert_eq!(kraken_legacy_name("USDT"), None);
+    }
+
+    #[test]
+    fn derived_symbols() {
+        let cases = [
+            (Exchange::Binance, "BTC/USD", "BTCUSDT"),
+            (Exchange::Binance, "ETH/EUR", "ETHEUR"),
+            (Exchange::Coinbase, "BTC/USD", "BTC-USD"),
+            (Exchange::Kraken, "BTC/USD", "XXBTZUSD"),
+            (Exchange::Kraken, "ETH/BTC", "XETHXXBT"),
+            (Exchange::Kraken, "DOGE/USD", "XDGUSD"),
+            (Exchange::Kraken, "SOL/USD", "SOLUSD"),
+            // Only legacy bases keep the prefixes, even with a legacy quote.
+            (Exchange::Kraken, "USD/ETH", "USDETH"),
+        ];
+        for &(exchange, pair_, symbol) in &cases {
+            assert_eq!(
+                derive_symbol(exchange, &pair(pair_)),
+                symbol,
+                "{} {}",
+                exchange,
+                pair_
+            );
+        }
+    }
+
+    #[test]
+    fn listed_symbols() {
+        let symbols = SymbolMap::new(Arc::new(Client::new()), Endpoints::default());
+        assert_eq!(
+            symbols
+                .native_symbol(Exchange::Binance, &pair("BTC/USD"))
+                .as_deref(),
+            Some("BTCUSDT")
+        );
+        let listing: HashMap<_, _> = vec![
+            (pair("BTC/USDT"), "BTCUSDT".to_owned()),
+            (pair("ETH/USD"), "ETHUSD".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        symbols
+            .listings
+            .write()
+            .unwrap()
+            .insert(Exchange::Binance, listing);
+        let native = |p: &str| symbols.native_symbol(Exchange::Binance, &pair(p));
+        // USD falls back to USDT, but only when USD itself isn't listed.
+        assert_eq!(native("BTC/USD").as_deref(), Some("BTCUSDT"));
+        assert_eq!(native("ETH/USD").as_deref(), Some("ETHUSD"));
+        assert_eq!(native("SOL/USD"), None);
+        assert_eq!(native("BTC/EUR"), None);
+        assert_eq!(
+            symbols
+                .native_symbol(Exchange::Kraken, &pair("BTC/USD"))
+                .as_deref(),
+            Some("XXBTZUSD")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Setup shared by tests that reach upstreams through a mock server.
+
+use std::sync::Arc;
+
+use reqwest::Client;
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::config::Config;
+use crate::fx::FxRates;
+use crate::resolver::Resolver;
+use crate::series::PriceSeries;
+use crate::symbols::SymbolMap;
+use crate::DataSources;
+
+/// Answers GET requests for `path_` whose `param` is `value` with `body`, a
+/// JSON fixture.
+pub async fn serve_fixture(
+    server: &MockServer,
+    path_: &str,
+    (param, value): (&str, &str),
+    body: &'static str,
+) {
+    Mock::given(method("GET"))
+        .and(path(path_))
+        .and(query_param(param, value))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+        .mount(server)
+        .await;
+}
+
+/// Binance's 24-hour ticker for BTCUSDT.
+pub async fn serve_binance(server: &MockServer) {
+    serve_fixture(
+        server,
+        "/api/v3/ticker/24hr",
+        ("symbol", "BTCUSDT"),
+        include_str!("../tests/fixtures/binance_ticker_24hr.json"),
+    )
+    .await;
+}
+
+/// `[[tickers]]` tables configuring each of `tickers` as Binance's
+/// `<ticker>USDT`.
+pub fn binance_tickers(tickers: &[&str]) -> String {
+    tickers
+        .iter()
+        .map(|ticker| {
+            format!(
+                "[[tickers]]\n\
+                 name = \"{0}\"\n\
+                 sources = [{{ type = \"binance\", symbol = \"{0}USDT\" }}]\n",
+                ticker
+            )
+        })
+        .collect()
+}
+
+/// A resolver reaching every upstream at `base_url`, with Yahoo Finance off
+/// and `tickers` (TOML `[[tickers]]` tables) configured.
+pub fn resolver(base_url: &str, tickers: &str) -> Resolver {
+    let tickers = if tickers.is_empty() {
+        "tickers = []"
+    } else {
+        tickers
+    };
+    let config: Config = toml::from_str(&format!(
+        "{1}\n\
+         [endpoints]\n\
+         binance = \"{0}\"\n\
+         coinbase = \"{0}\"\n\
+         kraken = \"{0}\"\n\
+         goldprice = \"{0}\"\n",
+        base_url, tickers
+    ))
+    .unwrap();
+    let client = Arc::new(Client::new());
+    let symbols = Arc::new(SymbolMap::new(client.clone(), config.endpoints.clone()));
+    Resolver::new(&config, client, None, symbols).unwrap()
+}
+
+/// Sources reaching every upstream at `base_url`, with each of `tickers`
+/// configured as Binance's `<ticker>USDT`. FX rates are unavailable, and
+/// nothing is stored or reported.
+pub fn data_sources(base_url: &str, tickers: &[&str]) -> DataSources {
+    DataSources {
+        default_tickers: tickers.iter().map(|t| t.to_string()).collect(),
+        resolver: resolver(base_url, &binance_tickers(tickers)),
+        series: PriceSeries::new(None).unwrap(),
+        history: None,
+        fx: FxRates::new(None),
+        error_reporter: None,
+    }
+}
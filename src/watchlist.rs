@@ -0,0 +1,137 @@
+use anyhow::Result;
+use futures::future::join_all;
+
+use crate::resolver::Resolver;
+use crate::storage::Storage;
+
+/// Longest watchlist a chat may keep.
+const MAX_WATCHLIST: usize = 20;
+
+const USAGE: &str = "Usage: /watch add <tickers...> | /watch remove <tickers...> | /watch list";
+
+/// Handles `/watch add|remove|list` for a chat and returns the reply.
+pub async fn handle_command(
+    args: &str,
+    chat_id: i64,
+    storage: &Storage,
+    resolver: &Resolver,
+) -> Result<String> {
+    let mut args = args.split_whitespace();
+    let action = args.next().unwrap_or("list").to_ascii_lowercase();
+    let tickers: Vec<_> = args.map(|s| s.to_ascii_uppercase()).collect();
+    match action.as_str() {
+        "list" => {
+            let watchlist = storage.watchlist(chat_id)?;
+            if watchlist.is_empty() {
+                Ok("Watchlist is empty, /query shows the default tickers".to_owned())
+            } else {
+                Ok(format!("Watchlist: {}", watchlist.join(" ")))
+            }
+        }
+        "add" if !tickers.is_empty() => {
+            let resolved = join_all(tickers.iter().map(|t| resolver.resolve(t))).await;
+            let unknown: Vec<_> = tickers
+                .iter()
+                .zip(resolved)
+                .filter(|(_, source)| source.is_none())
+                .map(|(ticker, _)| ticker.as_str())
+                .collect();
+            if !unknown.is_empty() {
+                return Ok(format!("Unknown tickers: {}", unknown.join(" ")));
+            }
+            let mut watchlist = storage.watchlist(chat_id)?;
+            watchlist.extend(tickers.iter().cloned());
+            watchlist.sort();
+            watchlist.dedup();
+            if watchlist.len() > MAX_WATCHLIST {
+                return Ok(format!("Watchlist is limited to {} tickers", MAX_WATCHLIST));
+            }
+            storage.watch_add(chat_id, &tickers)?;
+            Ok(format!(
+                "Watchlist: {}",
+                storage.watchlist(chat_id)?.join(" ")
+            ))
+        }
+        "remove" if !tickers.is_empty() => {
+            let removed = storage.watch_remove(chat_id, &tickers)?;
+            Ok(format!("Removed {} tickers", removed))
+        }
+        _ => Ok(USAGE.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::MockServer;
+
+    use super::{handle_command, MAX_WATCHLIST};
+    use crate::resolver::Resolver;
+    use crate::storage::Storage;
+    use crate::test_util::{self, binance_tickers};
+
+    /// Configures `count` tickers T0, T1, ...; anything else is unknown, as
+    /// the mock server has nothing to probe.
+    async fn setup(count: usize) -> (Storage, Resolver, MockServer) {
+        let server = MockServer::start().await;
+        let tickers: Vec<_> = (0..count).map(|i| format!("T{}", i)).collect();
+        let tickers: Vec<_> = tickers.iter().map(String::as_str).collect();
+        let resolver = test_util::resolver(&server.uri(), &binance_tickers(&tickers));
+        (Storage::open(":memory:").unwrap(), resolver, server)
+    }
+
+    #[tokio::test]
+    async fn add_list_remove() {
+        let (storage, resolver, _server) = setup(3).await;
+        let command = |args: &'static str| handle_command(args, 1, &storage, &resolver);
+        assert_eq!(
+            command("").await.unwrap(),
+            "Watchlist is empty, /query shows the default tickers"
+        );
+        assert_eq!(command("add t2 t0").await.unwrap(), "Watchlist: T2 T0");
+        assert_eq!(
+            command("add T0 T1 T1").await.unwrap(),
+            "Watchlist: T2 T0 T1"
+        );
+        assert_eq!(command("list").await.unwrap(), "Watchlist: T2 T0 T1");
+        assert_eq!(command("remove t0 t9").await.unwrap(), "Removed 1 tickers");
+        assert_eq!(command("list").await.unwrap(), "Watchlist: T2 T1");
+        assert!(command("add").await.unwrap().starts_with("Usage:"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_tickers() {
+        let (storage, resolver, _server) = setup(1).await;
+        let reply = handle_command("add T0 NOPE/NOPE", 1, &storage, &resolver)
+            .await
+            .unwrap();
+        assert_eq!(reply, "Unknown tickers: NOPE/NOPE");
+        assert!(storage.watchlist(1).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_length() {
+        let (storage, resolver, _server) = setup(MAX_WATCHLIST + 1).await;
+        let all: Vec<_> = (0..=MAX_WATCHLIST).map(|i| format!("T{}", i)).collect();
+        let full = format!("add {}", all[..MAX_WATCHLIST].join(" "));
+        handle_command(&full, 1, &storage, &resolver).await.unwrap();
+        assert_eq!(storage.watchlist(1).unwrap().len(), MAX_WATCHLIST);
+        // Tickers already on it still fit.
+        let reply = handle_command("add T0", 1, &storage, &resolver)
+            .await
+            .unwrap();
+        assert!(reply.starts_with("Watchlist: "), "{}", reply);
+        let reply = handle_command(
+            &format!("add {}", all[MAX_WATCHLIST]),
+            1,
+            &storage,
+            &resolver,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            reply,
+            format!("Watchlist is limited to {} tickers", MAX_WATCHLIST)
+        );
+        assert_eq!(storage.watchlist(1).unwrap().len(), MAX_WATCHLIST);
+    }
+}